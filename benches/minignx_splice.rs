@@ -0,0 +1,126 @@
+// minignx转发路径的吞吐和CPU对比: tokio::io::copy vs splice(2)
+// cargo bench --bench minignx_splice
+// 生产者和消费者运行在独立线程中，只统计转发所在线程的CPU时间
+
+#[cfg(target_os = "linux")]
+#[allow(dead_code)]
+#[path = "../examples/minignx/splice.rs"]
+mod splice;
+
+#[cfg(target_os = "linux")]
+fn main() -> anyhow::Result<()> {
+    linux::run()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("splice is only available on linux");
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use crate::splice::Pipe;
+    use anyhow::Result;
+    use std::{
+        io::{self, Write},
+        net::{Shutdown, TcpListener as StdListener, TcpStream as StdStream},
+        thread,
+        time::{Duration, Instant},
+    };
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+        runtime,
+    };
+
+    // 每轮转发的数据量
+    const TOTAL: u64 = 2 * 1024 * 1024 * 1024;
+    const ROUNDS: usize = 3;
+
+    #[derive(Debug, Clone, Copy)]
+    enum Mode {
+        Copy,
+        Splice,
+    }
+
+    pub fn run() -> Result<()> {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        for mode in [Mode::Copy, Mode::Splice] {
+            for _ in 0..ROUNDS {
+                let (wall, cpu) = rt.block_on(round(mode))?;
+                let gib = TOTAL as f64 / (1024.0 * 1024.0 * 1024.0);
+                println!(
+                    "{:<6?} {:>8.2} GiB/s  wall {:>6.2}s  cpu {:>6.2}s ({:.0}%)",
+                    mode,
+                    gib / wall.as_secs_f64(),
+                    wall.as_secs_f64(),
+                    cpu.as_secs_f64(),
+                    cpu.as_secs_f64() / wall.as_secs_f64() * 100.0
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // producer -> proxy -> sink，只转发一个方向
+    async fn round(mode: Mode) -> Result<(Duration, Duration)> {
+        let sink = StdListener::bind("127.0.0.1:0")?;
+        let sink_addr = sink.local_addr()?;
+        let consumer = thread::spawn(move || -> io::Result<u64> {
+            let (mut stream, _) = sink.accept()?;
+            io::copy(&mut stream, &mut io::sink())
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let proxy_addr = listener.local_addr()?;
+        let producer = thread::spawn(move || -> io::Result<()> {
+            let mut stream = StdStream::connect(proxy_addr)?;
+            let buf = vec![0u8; 256 * 1024];
+            let mut left = TOTAL;
+            while left > 0 {
+                let n = left.min(buf.len() as u64) as usize;
+                stream.write_all(&buf[..n])?;
+                left -= n as u64;
+            }
+            stream.shutdown(Shutdown::Write)
+        });
+
+        let (mut client, _) = listener.accept().await?;
+        let mut upstream = TcpStream::connect(sink_addr).await?;
+        let start = Instant::now();
+        let cpu = thread_cpu_time();
+        match mode {
+            Mode::Copy => {
+                tokio::io::copy(&mut client, &mut upstream).await?;
+            }
+            Mode::Splice => {
+                let pipe = Pipe::new()?;
+                loop {
+                    let n = pipe.splice_in(&client).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    pipe.splice_out(&upstream, n).await?;
+                }
+            }
+        }
+        upstream.shutdown().await?;
+        let cpu = thread_cpu_time() - cpu;
+        let wall = start.elapsed();
+
+        producer.join().expect("producer panicked")?;
+        let received = consumer.join().expect("consumer panicked")?;
+        assert_eq!(received, TOTAL);
+        Ok((wall, cpu))
+    }
+
+    fn thread_cpu_time() -> Duration {
+        let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+        // SAFETY: usage是有效的可写内存
+        unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) };
+        let tv = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+        tv(usage.ru_utime) + tv(usage.ru_stime)
+    }
+}
@@ -0,0 +1,72 @@
+use crate::config::{AccessLogConfig, Rotation};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{io::Write, net::SocketAddr, time::Duration};
+use tracing::warn;
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{self, RollingFileAppender},
+};
+
+// 每个会话结束时输出一条，按json lines写入滚动文件
+#[derive(Debug, Serialize)]
+pub struct AccessRecord {
+    pub timestamp: DateTime<Utc>,
+    pub protocol: &'static str,
+    pub client: SocketAddr,
+    pub listener: String,
+    // 连接upstream失败时为空
+    pub upstream: Option<String>,
+    pub connect_ms: Option<u64>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub duration_ms: u64,
+    pub close_reason: String,
+}
+
+pub struct AccessLog {
+    writer: NonBlocking,
+    // drop时把缓冲中的日志刷到文件，需要和AccessLog一起存活
+    _guard: WorkerGuard,
+}
+
+impl AccessLog {
+    // 目录无法创建或写入时返回错误
+    pub fn new(config: &AccessLogConfig) -> Result<Self> {
+        let rotation = match config.rotation {
+            Rotation::Minutely => rolling::Rotation::MINUTELY,
+            Rotation::Hourly => rolling::Rotation::HOURLY,
+            Rotation::Daily => rolling::Rotation::DAILY,
+            Rotation::Never => rolling::Rotation::NEVER,
+        };
+        let appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(&config.file_prefix)
+            .build(&config.directory)?;
+        // 写文件放到后台线程，不阻塞转发
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        Ok(Self {
+            writer,
+            _guard: guard,
+        })
+    }
+
+    pub fn log(&self, record: &AccessRecord) {
+        if let Err(e) = self.write(record) {
+            warn!("Failed to write access log: {}", e);
+        }
+    }
+
+    fn write(&self, record: &AccessRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        // 整行一次写入，多个会话并发记录时不会交错
+        self.writer.clone().write_all(&line)?;
+        Ok(())
+    }
+}
+
+pub fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
@@ -0,0 +1,38 @@
+use crate::config::AclConfig;
+use anyhow::{anyhow, Result};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+pub struct Acl {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl Acl {
+    pub fn try_new(config: &AclConfig) -> Result<Self> {
+        Ok(Self {
+            allow: parse_nets(&config.allow)?,
+            deny: parse_nets(&config.deny)?,
+        })
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        // 监听[::]时IPv4客户端地址是 ::ffff:a.b.c.d 的形式，先还原成IPv4再匹配
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+fn parse_nets(items: &[String]) -> Result<Vec<IpNet>> {
+    items
+        .iter()
+        .map(|item| {
+            item.parse::<IpNet>()
+                .or_else(|_| item.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| anyhow!("invalid ip or cidr in acl: {}", item))
+        })
+        .collect()
+}
@@ -0,0 +1,148 @@
+use crate::{
+    session::{Direction, Session},
+    state::State,
+    upstream::Upstream,
+};
+use axum::{
+    extract::{Path, State as AxumState},
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+#[derive(Debug, Serialize)]
+struct SessionView {
+    id: u64,
+    client: SocketAddr,
+    upstream: String,
+    client_to_upstream: u64,
+    upstream_to_client: u64,
+    started_at: DateTime<Utc>,
+    duration_ms: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct UpstreamView {
+    addr: String,
+    healthy: bool,
+    draining: bool,
+    active: u64,
+    connections: u64,
+    failures: u64,
+    client_to_upstream: u64,
+    upstream_to_client: u64,
+}
+
+// 监听socket由main绑定，绑定失败时启动直接报错；运行中的错误只能记录下来
+pub async fn serve(state: Arc<State>, listener: TcpListener) {
+    let app = Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(kill_session))
+        .route("/upstreams", get(list_upstreams))
+        .route("/upstreams/:addr/drain", post(drain_upstream))
+        .route("/upstreams/:addr/enable", post(enable_upstream))
+        .with_state(state);
+
+    if let Err(e) = axum::serve(listener, app.into_make_service()).await {
+        warn!("Admin api stopped: {}", e);
+    }
+}
+
+async fn list_sessions(AxumState(state): AxumState<Arc<State>>) -> impl IntoResponse {
+    let sessions: Vec<_> = state
+        .sessions
+        .list()
+        .iter()
+        .map(|s| SessionView::from(s.as_ref()))
+        .collect();
+    Json(sessions)
+}
+
+async fn kill_session(Path(id): Path<u64>, AxumState(state): AxumState<Arc<State>>) -> StatusCode {
+    match state.sessions.kill(id) {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+// 只列出当前配置中的upstream
+async fn list_upstreams(AxumState(state): AxumState<Arc<State>>) -> impl IntoResponse {
+    let settings = state.settings.load();
+    let mut seen = HashSet::new();
+    let upstreams: Vec<_> = settings
+        .upstreams
+        .iter()
+        .chain(settings.routes.iter().flat_map(|routes| routes.iter()))
+        .filter(|u| seen.insert(u.addr.clone()))
+        .map(|u| UpstreamView::from(u.as_ref()))
+        .collect();
+    Json(upstreams)
+}
+
+async fn drain_upstream(
+    Path(addr): Path<String>,
+    AxumState(state): AxumState<Arc<State>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    set_draining(&state, &addr, true)
+}
+
+async fn enable_upstream(
+    Path(addr): Path<String>,
+    AxumState(state): AxumState<Arc<State>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    set_draining(&state, &addr, false)
+}
+
+fn set_draining(
+    state: &State,
+    addr: &str,
+    draining: bool,
+) -> Result<Json<UpstreamView>, StatusCode> {
+    let upstream = state.upstreams.get(addr).ok_or(StatusCode::NOT_FOUND)?;
+    upstream.draining.store(draining, Ordering::Relaxed);
+    info!(
+        "Upstream {} is {} by admin",
+        addr,
+        if draining { "drained" } else { "enabled" }
+    );
+    Ok(Json(UpstreamView::from(upstream.as_ref())))
+}
+
+impl From<&Session> for SessionView {
+    fn from(s: &Session) -> Self {
+        Self {
+            id: s.id,
+            client: s.client,
+            upstream: s.upstream.addr.clone(),
+            client_to_upstream: s.bytes(Direction::ClientToUpstream),
+            upstream_to_client: s.bytes(Direction::UpstreamToClient),
+            started_at: s.started_at,
+            duration_ms: (Utc::now() - s.started_at).num_milliseconds(),
+        }
+    }
+}
+
+impl From<&Upstream> for UpstreamView {
+    fn from(u: &Upstream) -> Self {
+        Self {
+            addr: u.addr.clone(),
+            healthy: u.healthy.load(Ordering::Relaxed),
+            draining: u.draining.load(Ordering::Relaxed),
+            active: u.active.load(Ordering::Relaxed),
+            connections: u.connections.load(Ordering::Relaxed),
+            failures: u.failures.load(Ordering::Relaxed),
+            client_to_upstream: u.client_to_upstream.load(Ordering::Relaxed),
+            upstream_to_client: u.upstream_to_client.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationMilliSeconds};
+use std::{fs, path::Path, path::PathBuf, time::Duration};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    // 按轮询选择upstream，连接失败时依次重试下一个
+    pub upstreams: Vec<String>,
+    pub listen_addr: String,
+    // 监听协议，udp模式下tls/proxy_protocol/单IP连接数限制不生效，max_connections限制会话数
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    #[serde(default)]
+    pub limits: LimitConfig,
+    #[serde(default)]
+    pub acl: AclConfig,
+    #[serde(default)]
+    pub proxy_protocol: ProxyProtocolConfig,
+    // 管理接口，只在启动时读取
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    // 仅linux: 明文tcp转发时用splice(2)在内核中搬运数据，不支持时自动退回用户态复制
+    // 启用tls终止或mirror的会话仍然走用户态复制
+    #[serde(default)]
+    pub splice: bool,
+    // 把client发来的数据复制给shadow upstream，用于验证新版本后端
+    #[serde(default)]
+    pub mirror: Option<MirrorConfig>,
+    // prometheus指标，只在启动时读取
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+    // 会话访问日志，和调试日志分开输出，只在启动时读取
+    #[serde(default)]
+    pub access_log: Option<AccessLogConfig>,
+    // 配置了tls则在监听端终止TLS，转发给upstream的仍是明文
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    // 不终止TLS，按ClientHello中的SNI选择upstream组，没有匹配的走upstreams
+    #[serde(default)]
+    pub sni_routes: Vec<SniRoute>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SniRoute {
+    // 支持 *.example.com 形式的通配符
+    pub server_names: Vec<String>,
+    pub upstreams: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TlsConfig {
+    pub certs: Vec<CertConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CertConfig {
+    // 根据ClientHello中的SNI选择证书，支持 *.example.com 形式的通配符
+    // 为空时作为默认证书，用于没有SNI或没有匹配的连接
+    #[serde(default)]
+    pub server_names: Vec<String>,
+    // PEM格式的证书链与私钥文件路径
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    // 单次连接upstream的超时时间
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "connect_timeout_ms")]
+    pub connect_timeout: Duration,
+    // 首次连接失败后的重试次数，每次重试换下一个upstream
+    pub connect_retries: u32,
+    // 重试前的等待时间，每次重试翻倍
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "retry_backoff_ms")]
+    pub retry_backoff: Duration,
+    // client -> upstream 方向等待数据的超时，另一个方向在转发数据时不算空闲，不配置则不限制
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(rename = "client_idle_timeout_ms")]
+    pub client_idle_timeout: Option<Duration>,
+    // upstream -> client 方向读写空闲超时，不配置则不限制
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(rename = "upstream_idle_timeout_ms")]
+    pub upstream_idle_timeout: Option<Duration>,
+    // 单个会话的最长存活时间，不配置则不限制
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(rename = "max_session_ms")]
+    pub max_session: Option<Duration>,
+    // udp会话在两个方向都没有数据后多久过期
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "udp_session_timeout_ms")]
+    pub udp_session_timeout: Duration,
+    // 收到退出信号后等待已有会话结束的最长时间
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "drain_timeout_ms")]
+    pub drain_timeout: Duration,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(3),
+            connect_retries: 2,
+            retry_backoff: Duration::from_millis(100),
+            client_idle_timeout: None,
+            upstream_idle_timeout: None,
+            max_session: None,
+            udp_session_timeout: Duration::from_secs(30),
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct LimitConfig {
+    // 全局最大并发连接数，不配置则不限制
+    pub max_connections: Option<usize>,
+    // 达到全局上限后新连接的处理方式
+    pub overflow: Overflow,
+    // 单个客户端IP的最大并发连接数，超过直接拒绝
+    pub max_connections_per_ip: Option<usize>,
+    // 每个连接每个方向的带宽上限(字节/秒)
+    pub bandwidth_per_connection: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    // 暂停accept，新连接在内核的backlog中排队
+    #[default]
+    Queue,
+    // accept后立即关闭
+    Reject,
+}
+
+// 条目可以是单个IP或CIDR网段，deny优先；allow非空时只放行匹配allow的客户端
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AclConfig {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ProxyProtocolConfig {
+    // minignx在其他负载均衡之后时，从client连接读取PROXY头获得真实客户端地址
+    pub accept: bool,
+    // 连接upstream后先发送PROXY头，让upstream看到真实客户端地址
+    pub send: Option<ProxyProtocolVersion>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AdminConfig {
+    // 管理接口没有鉴权，只应监听在内网或本机地址
+    pub listen_addr: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MetricsConfig {
+    // 在 http://<listen_addr>/metrics 输出prometheus文本格式
+    pub listen_addr: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MirrorConfig {
+    pub upstream: String,
+    // 每个会话最多积压的数据块数(每块不超过8KB)，超过后该会话停止复制
+    #[serde(default = "default_mirror_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_mirror_queue_size() -> usize {
+    256
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AccessLogConfig {
+    pub directory: PathBuf,
+    #[serde(default = "default_access_log_prefix")]
+    pub file_prefix: String,
+    #[serde(default)]
+    pub rotation: Rotation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+fn default_access_log_prefix() -> String {
+    "access.log".to_string()
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str(&content)?)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            upstreams: vec!["127.0.0.1:8080".to_string()],
+            listen_addr: "127.0.0.1:8081".to_string(),
+            protocol: Protocol::default(),
+            timeouts: TimeoutConfig::default(),
+            limits: LimitConfig::default(),
+            acl: AclConfig::default(),
+            proxy_protocol: ProxyProtocolConfig::default(),
+            admin: None,
+            splice: false,
+            mirror: None,
+            metrics: None,
+            access_log: None,
+            tls: None,
+            sni_routes: vec![],
+        }
+    }
+}
+
+// 第一个命令行参数为yaml配置文件路径
+pub fn config_path() -> Option<PathBuf> {
+    std::env::args().nth(1).map(PathBuf::from)
+}
+
+// 没有配置文件则使用默认配置
+pub fn resolve_config(path: Option<&Path>) -> Result<Config> {
+    match path {
+        Some(path) => Config::load(path),
+        None => Ok(Config::default()),
+    }
+}
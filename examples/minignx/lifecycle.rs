@@ -0,0 +1,230 @@
+use anyhow::Result;
+use std::time::Duration;
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    time,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
+
+#[cfg(unix)]
+use std::{
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    process::Child,
+};
+
+// 新进程准备好接收连接前旧进程最多等待的时间，超时则杀掉新进程继续服务
+#[cfg(unix)]
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 新进程绑定完所有socket后往这个fd写一个字节，告诉旧进程可以退出了
+#[cfg(unix)]
+const READY_FD_ENV: &str = "MINIGNX_READY_FD";
+
+// 升级时交给新进程的socket，各自通过一个环境变量告诉新进程继承下来的fd
+// 继承的socket沿用旧进程的监听地址，升级时修改对应的listen_addr不会生效
+#[derive(Debug, Clone, Copy)]
+pub enum Role {
+    Listen,
+    Admin,
+    Metrics,
+}
+
+#[cfg(unix)]
+impl Role {
+    const ALL: [Role; 3] = [Role::Listen, Role::Admin, Role::Metrics];
+
+    fn env(self) -> &'static str {
+        match self {
+            Role::Listen => "MINIGNX_LISTEN_FD",
+            Role::Admin => "MINIGNX_ADMIN_FD",
+            Role::Metrics => "MINIGNX_METRICS_FD",
+        }
+    }
+}
+
+// 收到SIGUSR2时要交给新进程的所有socket
+#[derive(Debug, Default)]
+pub struct Handover {
+    #[cfg(unix)]
+    fds: Vec<(Role, RawFd)>,
+}
+
+impl Handover {
+    #[cfg(unix)]
+    pub fn add(&mut self, role: Role, socket: &impl AsRawFd) {
+        self.fds.push((role, socket.as_raw_fd()));
+    }
+
+    #[cfg(not(unix))]
+    pub fn add<S>(&mut self, _role: Role, _socket: &S) {}
+}
+
+// 优先使用父进程传下来的监听socket，否则重新绑定
+pub async fn bind_tcp(role: Role, addr: &str) -> Result<TcpListener> {
+    #[cfg(unix)]
+    if let Some(fd) = inherited_fd(role)? {
+        // SAFETY: fd由旧进程在exec前去掉了CLOEXEC传入，是一个正在监听的tcp socket
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        info!("Inherited {:?} socket {} from old process", role, fd);
+        return Ok(TcpListener::from_std(listener)?);
+    }
+    Ok(TcpListener::bind(addr).await?)
+}
+
+pub async fn bind_udp(addr: &str) -> Result<UdpSocket> {
+    #[cfg(unix)]
+    if let Some(fd) = inherited_fd(Role::Listen)? {
+        // SAFETY: 同bind_tcp，是一个已绑定的udp socket
+        let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+        socket.set_nonblocking(true)?;
+        info!("Inherited udp socket {} from old process", fd);
+        return Ok(UdpSocket::from_std(socket)?);
+    }
+    Ok(UdpSocket::bind(addr).await?)
+}
+
+#[cfg(unix)]
+fn inherited_fd(role: Role) -> Result<Option<RawFd>> {
+    match std::env::var(role.env()) {
+        Ok(fd) => Ok(Some(fd.parse()?)),
+        Err(_) => Ok(None),
+    }
+}
+
+// 所有socket都绑定好之后调用，由升级启动时通知旧进程退出
+pub fn notify_ready() -> Result<()> {
+    #[cfg(unix)]
+    if let Ok(fd) = std::env::var(READY_FD_ENV) {
+        use std::io::Write;
+
+        // SAFETY: fd是旧进程传入的pipe写端，只在这里使用一次
+        let mut pipe = unsafe { std::fs::File::from_raw_fd(fd.parse()?) };
+        pipe.write_all(b"1")?;
+    }
+    Ok(())
+}
+
+// SIGTERM/SIGINT: 停止接收新连接并等待已有会话结束
+// SIGUSR2: 用同样的参数启动新的二进制并把socket交给它，新进程就绪后和SIGTERM一样退出
+// 旧进程drain期间和新进程共用管理接口与指标的监听socket，请求可能落到任一进程
+#[cfg(unix)]
+pub fn watch_signals(handover: Handover, shutdown: CancellationToken) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut upgrade = signal(SignalKind::user_defined2())?;
+        loop {
+            tokio::select! {
+                _ = terminate.recv() => break,
+                _ = interrupt.recv() => break,
+                _ = upgrade.recv() => match upgrade_to_new_process(&handover).await {
+                    Ok(pid) => {
+                        info!("New process {} is ready, handing over the listening sockets", pid);
+                        break;
+                    }
+                    // 新进程启动失败或没有就绪则继续服务
+                    Err(e) => warn!("Failed to upgrade: {:?}", e),
+                },
+            }
+        }
+        info!("Shutting down, stop accepting new connections");
+        shutdown.cancel();
+        Ok::<(), anyhow::Error>(())
+    });
+}
+
+#[cfg(not(unix))]
+pub fn watch_signals(_handover: Handover, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await?;
+        info!("Shutting down, stop accepting new connections");
+        shutdown.cancel();
+        Ok::<(), anyhow::Error>(())
+    });
+}
+
+// 启动新进程并等它就绪，新进程先退出(比如配置错误或端口冲突)时返回错误
+#[cfg(unix)]
+async fn upgrade_to_new_process(handover: &Handover) -> Result<u32> {
+    use tokio::{io::AsyncReadExt, net::unix::pipe};
+
+    let (ready, notify) = ready_pipe()?;
+    let mut child = spawn_upgrade(handover, notify)?;
+    let pid = child.id();
+    let mut ready = pipe::Receiver::from_owned_fd(ready)?;
+    let mut buf = [0u8; 1];
+    // 新进程退出后pipe写端随之关闭，这里读到EOF
+    let ret = match time::timeout(READY_TIMEOUT, ready.read(&mut buf)).await {
+        Ok(Ok(1)) => return Ok(pid),
+        Ok(Ok(_)) => Err(anyhow::anyhow!("new process {} exited before ready", pid)),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Err(anyhow::anyhow!("new process {} is not ready in time", pid)),
+    };
+    // 不让没就绪的新进程和旧进程一起接收连接
+    let _ = child.kill();
+    let _ = child.wait();
+    ret
+}
+
+#[cfg(unix)]
+fn ready_pipe() -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: fds有两个元素，成功后由OwnedFd负责关闭
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    unsafe { Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) }
+}
+
+// notify在函数返回时drop，旧进程只剩读端，新进程退出时读端能读到EOF
+#[cfg(unix)]
+fn spawn_upgrade(handover: &Handover, notify: OwnedFd) -> Result<Child> {
+    use std::{io, os::unix::process::CommandExt, process::Command};
+
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.args(std::env::args_os().skip(1));
+    // 本进程自己是升级启动的话环境变量里还有旧的fd，先清掉
+    for role in Role::ALL {
+        cmd.env_remove(role.env());
+    }
+    for (role, fd) in &handover.fds {
+        cmd.env(role.env(), fd.to_string());
+    }
+    cmd.env(READY_FD_ENV, notify.as_raw_fd().to_string());
+
+    let mut fds: Vec<_> = handover.fds.iter().map(|(_, fd)| *fd).collect();
+    fds.push(notify.as_raw_fd());
+    // rust创建的fd默认带CLOEXEC，在子进程exec前去掉，让新进程继承这些socket和pipe
+    // SAFETY: pre_exec在fork出的子进程中执行，fcntl是async-signal-safe的
+    unsafe {
+        cmd.pre_exec(move || {
+            for &fd in &fds {
+                if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(cmd.spawn()?)
+}
+
+// 最多等待deadline让已有会话自然结束，超时后剩余会话随进程退出被关闭
+pub async fn drain(tracker: TaskTracker, deadline: Duration) {
+    tracker.close();
+    if tracker.is_empty() {
+        return;
+    }
+    info!("Waiting for {} active sessions to finish", tracker.len());
+    match time::timeout(deadline, tracker.wait()).await {
+        Ok(_) => info!("All sessions finished"),
+        Err(_) => warn!(
+            "Drain deadline reached, closing {} remaining sessions",
+            tracker.len()
+        ),
+    }
+}
@@ -0,0 +1,114 @@
+use crate::config::{LimitConfig, Overflow};
+use dashmap::DashMap;
+use std::{net::IpAddr, sync::Arc, time::Duration};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{self, Instant},
+};
+use tracing::warn;
+
+pub struct ConnectionLimiter {
+    config: LimitConfig,
+    global: Option<Arc<Semaphore>>,
+    per_ip: DashMap<IpAddr, usize>,
+}
+
+// 连接结束时drop，归还全局和单IP的配额
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+    _global: Option<OwnedSemaphorePermit>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: LimitConfig) -> Self {
+        let global = config
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+        Self {
+            config,
+            global,
+            per_ip: DashMap::new(),
+        }
+    }
+
+    // 返回None表示连接应被拒绝
+    pub async fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionPermit> {
+        if !self.try_acquire_ip(ip) {
+            warn!("Too many connections from {}, rejected", ip);
+            return None;
+        }
+        // 先占用单IP配额，之后无论是否拿到全局配额都由permit负责归还
+        let mut permit = ConnectionPermit {
+            limiter: self.clone(),
+            ip,
+            _global: None,
+        };
+        if let Some(global) = &self.global {
+            let acquired = match self.config.overflow {
+                Overflow::Queue => global.clone().acquire_owned().await.ok(),
+                Overflow::Reject => global.clone().try_acquire_owned().ok(),
+            };
+            if acquired.is_none() {
+                warn!("Max connections reached, rejected {}", ip);
+                return None;
+            }
+            permit._global = acquired;
+        }
+        Some(permit)
+    }
+
+    fn try_acquire_ip(&self, ip: IpAddr) -> bool {
+        let mut count = self.per_ip.entry(ip).or_insert(0);
+        match self.config.max_connections_per_ip {
+            Some(max) if *count >= max => false,
+            _ => {
+                *count += 1;
+                true
+            }
+        }
+    }
+
+    fn release_ip(&self, ip: IpAddr) {
+        self.per_ip.remove_if_mut(&ip, |_, count| {
+            *count -= 1;
+            *count == 0
+        });
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release_ip(self.ip);
+    }
+}
+
+// 按固定速率匀速转发，发送超前时sleep等待
+pub struct Throttle {
+    rate: u64,
+    start: Instant,
+    sent: u64,
+}
+
+impl Throttle {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            start: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    pub async fn consume(&mut self, n: usize) {
+        let now = Instant::now();
+        let due = self.start + Duration::from_secs_f64(self.sent as f64 / self.rate as f64);
+        // 空闲过后重新计时，避免积攒的额度造成突发
+        if due < now {
+            self.start = now;
+            self.sent = 0;
+        }
+        self.sent += n as u64;
+        let due = self.start + Duration::from_secs_f64(self.sent as f64 / self.rate as f64);
+        time::sleep_until(due).await;
+    }
+}
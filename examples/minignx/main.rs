@@ -0,0 +1,335 @@
+// it could be a proxy to a upstream
+mod access_log;
+mod acl;
+mod admin;
+mod config;
+mod lifecycle;
+mod limit;
+mod metrics;
+mod mirror;
+mod proxy;
+mod proxy_protocol;
+mod session;
+mod sni;
+#[cfg(target_os = "linux")]
+mod splice;
+mod state;
+#[cfg(test)]
+mod tests;
+mod tls;
+mod udp;
+mod upstream;
+
+use access_log::{millis, AccessRecord};
+use anyhow::Result;
+use chrono::Utc;
+use config::{config_path, Protocol};
+use lifecycle::{Handover, Role};
+use mirror::Mirror;
+use proxy::{proxy, ClientStream};
+use proxy_protocol::ProxyHeader;
+use session::Direction;
+use state::{Settings, State};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    time,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, info, info_span, warn, Instrument};
+use tracing_subscriber::{
+    fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer as _,
+};
+use upstream::Upstreams;
+
+// windows系统使用0.0.0.0:8080不行，该地址用于本地监听，不用于外部连接而127.0.0.1则是回环地址
+// localhost会出现DNS解析问题，TcpStream::connect(upstream_addr)连接十分缓慢
+// use tokio runtime to do a proxy server
+#[tokio::main]
+async fn main() -> Result<()> {
+    // 调试日志级别由RUST_LOG控制，会话记录单独写入access_log
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let layer = Layer::new().with_filter(filter);
+    tracing_subscriber::registry().with(layer).init();
+
+    let state = Arc::new(State::try_new(config_path())?);
+    let settings = state.settings.load_full();
+    info!("Upstreams are {:?}", settings.config.upstreams);
+    info!("Listening on {}", settings.config.listen_addr);
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(state.clone()));
+
+    // 升级时这些socket都交给新进程，新进程绑定完毕后旧进程才退出
+    let mut handover = Handover::default();
+    if let Some(admin) = &settings.config.admin {
+        let listener = lifecycle::bind_tcp(Role::Admin, &admin.listen_addr).await?;
+        info!("Admin api listening on {}", admin.listen_addr);
+        handover.add(Role::Admin, &listener);
+        tokio::spawn(admin::serve(state.clone(), listener));
+    }
+    if let Some(metrics) = &settings.config.metrics {
+        let listener = lifecycle::bind_tcp(Role::Metrics, &metrics.listen_addr).await?;
+        info!("Metrics listening on {}", metrics.listen_addr);
+        handover.add(Role::Metrics, &listener);
+        tokio::spawn(metrics::serve(state.clone(), listener));
+    }
+
+    // 收到退出或升级信号时取消，所有会话任务由tracker跟踪以便退出前等待
+    let shutdown = CancellationToken::new();
+    let tracker = TaskTracker::new();
+    match settings.config.protocol {
+        Protocol::Udp => {
+            let socket = lifecycle::bind_udp(&settings.config.listen_addr).await?;
+            handover.add(Role::Listen, &socket);
+            lifecycle::notify_ready()?;
+            lifecycle::watch_signals(handover, shutdown.clone());
+            udp::serve(state.clone(), socket, shutdown, tracker.clone()).await?;
+        }
+        Protocol::Tcp => {
+            let listener = lifecycle::bind_tcp(Role::Listen, &settings.config.listen_addr).await?;
+            handover.add(Role::Listen, &listener);
+            lifecycle::notify_ready()?;
+            lifecycle::watch_signals(handover, shutdown.clone());
+            serve_tcp(state.clone(), listener, shutdown, tracker.clone()).await?;
+        }
+    }
+
+    // 退出前按最新配置的drain时间等待
+    let deadline = state.settings.load().config.timeouts.drain_timeout;
+    lifecycle::drain(tracker, deadline).await;
+    Ok(())
+}
+
+async fn serve_tcp(
+    state: Arc<State>,
+    listener: TcpListener,
+    shutdown: CancellationToken,
+    tracker: TaskTracker,
+) -> Result<()> {
+    loop {
+        // accept的错误多是暂时的，只有shutdown结束循环
+        let (client, addr) = tokio::select! {
+            ret = listener.accept() => match ret {
+                Ok(ret) => ret,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    // 文件描述符耗尽时立即重试只会空转，等待已有连接释放
+                    if matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) {
+                        time::sleep(Duration::from_millis(100)).await;
+                    }
+                    continue;
+                }
+            },
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        // 每个连接取一份当前配置的快照，之后的重载不影响已建立的连接
+        let settings = state.settings.load_full();
+
+        if !settings.acl.is_allowed(addr.ip()) {
+            warn!("Denied connection from {} by acl", addr);
+            state.metrics.inc_connections("rejected");
+            continue;
+        }
+        debug!("Accepted connection from {}", addr);
+        // 超过连接数限制的连接直接drop关闭；排队模式下会在这里等待空闲配额
+        let permit = tokio::select! {
+            permit = settings.limiter.acquire(addr.ip()) => permit,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        let Some(permit) = permit else {
+            state.metrics.inc_connections("rejected");
+            continue;
+        };
+        state.metrics.inc_connections("accepted");
+        let state = state.clone();
+        tracker.spawn(async move {
+            let _permit = permit;
+            if let Err(e) = serve(client, addr, &state, &settings).await {
+                warn!("Failed to serve {}: {:?}", addr, e);
+                state.metrics.inc_connections("failed");
+            }
+        });
+    }
+}
+
+// 无论在哪一步失败都写一条访问日志，错误作为close_reason
+async fn serve(
+    client: TcpStream,
+    addr: SocketAddr,
+    state: &State,
+    settings: &Settings,
+) -> Result<()> {
+    let start = Instant::now();
+    let mut record = AccessRecord {
+        timestamp: Utc::now(),
+        protocol: "tcp",
+        client: addr,
+        listener: settings.config.listen_addr.clone(),
+        upstream: None,
+        connect_ms: None,
+        bytes_in: 0,
+        bytes_out: 0,
+        duration_ms: 0,
+        close_reason: String::new(),
+    };
+    let ret = serve_client(client, addr, state, settings, &mut record).await;
+    record.duration_ms = millis(start.elapsed());
+    if let Err(e) = &ret {
+        record.close_reason = e.to_string();
+    }
+    state.log_access(record);
+    ret
+}
+
+async fn serve_client(
+    mut client: TcpStream,
+    addr: SocketAddr,
+    state: &State,
+    settings: &Settings,
+    record: &mut AccessRecord,
+) -> Result<()> {
+    let config = &settings.config;
+    let mut peer = ProxyHeader {
+        src: addr,
+        dst: client.local_addr()?,
+    };
+    // PROXY头在TLS握手之前，读取时间受connect_timeout限制
+    if config.proxy_protocol.accept {
+        let header = time::timeout(
+            config.timeouts.connect_timeout,
+            proxy_protocol::read_header(&mut client),
+        )
+        .await??;
+        if let Some(header) = header {
+            debug!("Connection from {} is proxied for {}", addr, header.src);
+            record.client = header.src;
+            peer = header;
+        }
+    }
+
+    // 之后的日志都带上真实的客户端地址
+    let span = info_span!("session", client = %peer.src);
+    if let Some(routes) = &settings.routes {
+        // 读出ClientHello选择upstream组，读到的字节在连接upstream后先转发过去
+        let mut hello = Vec::new();
+        let name = time::timeout(
+            config.timeouts.connect_timeout,
+            sni::read_server_name(&mut client, &mut hello),
+        )
+        .await??;
+        let upstreams = name
+            .as_deref()
+            .and_then(|name| routes.route(name))
+            .unwrap_or(&settings.upstreams);
+        debug!("Routing {:?} from {}", name, peer.src);
+        return handle_client(client, &peer, upstreams, &hello, state, settings, record)
+            .instrument(span)
+            .await;
+    }
+    match &settings.acceptor {
+        Some(acceptor) => {
+            // 先完成与client的TLS握手，再连接upstream转发明文；握手时间同样受connect_timeout限制
+            let client =
+                time::timeout(config.timeouts.connect_timeout, acceptor.accept(client)).await??;
+            handle_client(
+                client,
+                &peer,
+                &settings.upstreams,
+                &[],
+                state,
+                settings,
+                record,
+            )
+            .instrument(span)
+            .await
+        }
+        None => {
+            handle_client(
+                client,
+                &peer,
+                &settings.upstreams,
+                &[],
+                state,
+                settings,
+                record,
+            )
+            .instrument(span)
+            .await
+        }
+    }
+}
+
+// prefix是转发前已经从client读出的数据，要在代理开始前先发给upstream
+async fn handle_client<C>(
+    client: C,
+    peer: &ProxyHeader,
+    upstreams: &Upstreams,
+    prefix: &[u8],
+    state: &State,
+    settings: &Settings,
+    record: &mut AccessRecord,
+) -> Result<()>
+where
+    C: ClientStream,
+{
+    let config = &settings.config;
+    let start = Instant::now();
+    let (mut upstream, target) = upstreams.connect(&config.timeouts, &state.metrics).await?;
+    record.upstream = Some(target.addr.clone());
+    record.connect_ms = Some(millis(start.elapsed()));
+    debug!("Connected to upstream {}", target.addr);
+    // 注册到活跃会话列表，供管理接口查询和kill
+    let guard = state.sessions.register(peer.src, target);
+    let mut mirror = config
+        .mirror
+        .as_ref()
+        .map(|mirror| Mirror::spawn(mirror, config.timeouts.connect_timeout));
+    if let Some(version) = config.proxy_protocol.send {
+        let header = proxy_protocol::encode_header(version, peer);
+        upstream.write_all(&header).await?;
+        if let Some(mirror) = &mut mirror {
+            mirror.send(&header);
+        }
+    }
+    if !prefix.is_empty() {
+        upstream.write_all(prefix).await?;
+        if let Some(mirror) = &mut mirror {
+            mirror.send(prefix);
+        }
+        guard
+            .session
+            .add_bytes(Direction::ClientToUpstream, prefix.len() as u64);
+    }
+    let proxied_at = Instant::now();
+    let transfer = proxy(client, upstream, config, &guard.session, mirror).await;
+    state
+        .metrics
+        .session_duration
+        .with_label_values(&[&guard.session.upstream.addr])
+        .observe(proxied_at.elapsed().as_secs_f64());
+
+    record.bytes_in = transfer.client_to_upstream;
+    record.bytes_out = transfer.upstream_to_client;
+    record.close_reason = transfer.close.to_string();
+    Ok(())
+}
+
+// kill -HUP <pid> 重新加载配置文件
+#[cfg(unix)]
+async fn reload_on_sighup(state: Arc<State>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        if let Err(e) = state.reload() {
+            warn!("Failed to reload config: {:?}", e);
+        }
+    }
+    Ok(())
+}
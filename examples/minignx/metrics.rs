@@ -0,0 +1,131 @@
+use crate::{state::State, upstream::Upstream};
+use anyhow::Result;
+use axum::{extract::State as AxumState, routing::get, Router};
+use dashmap::DashMap;
+use http::StatusCode;
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::sync::{atomic::Ordering, Arc};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+// 会话时长的分桶(秒)，长连接可能持续数小时
+const SESSION_BUCKETS: &[f64] = &[
+    0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0, 14400.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    // status: accepted | rejected | failed
+    pub connections: IntCounterVec,
+    pub connect_duration: HistogramVec,
+    pub session_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn try_new(upstreams: Arc<DashMap<String, Arc<Upstream>>>) -> Result<Self> {
+        let connections = IntCounterVec::new(
+            Opts::new("minignx_connections_total", "Client connections by status"),
+            &["status"],
+        )?;
+        let connect_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "minignx_upstream_connect_duration_seconds",
+                "Time to establish a tcp connection to the upstream",
+            ),
+            &["upstream"],
+        )?;
+        let session_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "minignx_session_duration_seconds",
+                "Duration of proxied sessions",
+            )
+            .buckets(SESSION_BUCKETS.to_vec()),
+            &["upstream"],
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(connections.clone()))?;
+        registry.register(Box::new(connect_duration.clone()))?;
+        registry.register(Box::new(session_duration.clone()))?;
+        registry.register(Box::new(UpstreamBytes::try_new(upstreams)?))?;
+        Ok(Self {
+            registry,
+            connections,
+            connect_duration,
+            session_duration,
+        })
+    }
+
+    pub fn inc_connections(&self, status: &str) {
+        self.connections.with_label_values(&[status]).inc();
+    }
+
+    fn encode(&self) -> Result<String> {
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
+
+// upstream的字节数已经在转发时实时累加，采集时直接读取
+struct UpstreamBytes {
+    upstreams: Arc<DashMap<String, Arc<Upstream>>>,
+    desc: Desc,
+}
+
+impl UpstreamBytes {
+    fn try_new(upstreams: Arc<DashMap<String, Arc<Upstream>>>) -> Result<Self> {
+        let desc = Desc::new(
+            "minignx_upstream_bytes_total".to_string(),
+            "Bytes proxied per upstream and direction".to_string(),
+            vec!["upstream".to_string(), "direction".to_string()],
+            Default::default(),
+        )?;
+        Ok(Self { upstreams, desc })
+    }
+}
+
+impl Collector for UpstreamBytes {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let Ok(bytes) = IntCounterVec::new(
+            Opts::new(&self.desc.fq_name, &self.desc.help),
+            &["upstream", "direction"],
+        ) else {
+            return vec![];
+        };
+        for upstream in self.upstreams.iter() {
+            let addr = upstream.addr.as_str();
+            bytes
+                .with_label_values(&[addr, "client_to_upstream"])
+                .inc_by(upstream.client_to_upstream.load(Ordering::Relaxed));
+            bytes
+                .with_label_values(&[addr, "upstream_to_client"])
+                .inc_by(upstream.upstream_to_client.load(Ordering::Relaxed));
+        }
+        bytes.collect()
+    }
+}
+
+// 同admin::serve，监听socket由main绑定
+pub async fn serve(state: Arc<State>, listener: TcpListener) {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(state);
+
+    if let Err(e) = axum::serve(listener, app.into_make_service()).await {
+        warn!("Metrics server stopped: {}", e);
+    }
+}
+
+async fn metrics(AxumState(state): AxumState<Arc<State>>) -> Result<String, StatusCode> {
+    state
+        .metrics
+        .encode()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,81 @@
+use crate::config::MirrorConfig;
+use bytes::Bytes;
+use std::{pin::pin, time::Duration};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc::{self, error::TrySendError, Sender},
+    time,
+};
+use tracing::{debug, warn};
+
+// 会话结束后最多再等shadow这么久
+const SHADOW_LINGER: Duration = Duration::from_secs(5);
+
+// 把client发往upstream的数据复制一份发给shadow upstream，shadow的响应直接丢弃
+// 主路径只往有界队列里try_send，shadow慢或者不可用都不会阻塞转发
+pub struct Mirror {
+    tx: Option<Sender<Bytes>>,
+}
+
+impl Mirror {
+    pub fn spawn(config: &MirrorConfig, connect_timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        tokio::spawn(run(config.upstream.clone(), connect_timeout, rx));
+        Self { tx: Some(tx) }
+    }
+
+    // 队列满或shadow已断开时停止复制，丢了中间的数据后再发给shadow已经没有意义
+    pub fn send(&mut self, data: &[u8]) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        match tx.try_send(Bytes::copy_from_slice(data)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!("Shadow upstream is lagging, stop mirroring");
+                self.tx = None;
+            }
+            Err(TrySendError::Closed(_)) => self.tx = None,
+        }
+    }
+}
+
+async fn run(addr: String, connect_timeout: Duration, rx: mpsc::Receiver<Bytes>) {
+    let stream = match time::timeout(connect_timeout, TcpStream::connect(&addr)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            warn!("Failed to connect to shadow upstream {}: {}", addr, e);
+            return;
+        }
+        Err(_) => {
+            warn!("Timed out connecting to shadow upstream {}", addr);
+            return;
+        }
+    };
+    if let Err(e) = relay(stream, rx).await {
+        debug!("Shadow upstream {} error: {}", addr, e);
+    }
+}
+
+async fn relay(stream: TcpStream, mut rx: mpsc::Receiver<Bytes>) -> io::Result<()> {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut sink = io::sink();
+    let mut discard = pin!(io::copy(&mut reader, &mut sink));
+    let write = async {
+        while let Some(data) = rx.recv().await {
+            writer.write_all(&data).await?;
+        }
+        // client写端关闭后，把FIN也传给shadow
+        writer.shutdown().await
+    };
+    tokio::select! {
+        ret = write => {
+            ret?;
+            // 等shadow把响应发完再关闭，避免它写到一半收到RST
+            let _ = time::timeout(SHADOW_LINGER, discard).await;
+            Ok(())
+        }
+        ret = &mut discard => ret.map(|_| ()),
+    }
+}
@@ -0,0 +1,364 @@
+use crate::{
+    config::Config,
+    limit::Throttle,
+    mirror::Mirror,
+    session::{Direction, Session},
+};
+use std::{fmt, future::Future, pin::pin, time::Duration};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+use tokio_rustls::server::TlsStream;
+use tracing::debug;
+
+#[cfg(target_os = "linux")]
+use crate::splice::{self, Pipe};
+#[cfg(target_os = "linux")]
+use tokio::net::tcp::{ReadHalf, WriteHalf};
+
+// 一次会话的结果，无论是否出错都带上两个方向已转发的字节数
+#[derive(Debug)]
+pub struct Transfer {
+    pub client_to_upstream: u64,
+    pub upstream_to_client: u64,
+    pub close: CloseReason,
+}
+
+#[derive(Debug)]
+pub enum CloseReason {
+    // 两个方向都读到EOF并把FIN传给了对端
+    Normal,
+    ClientReset,
+    UpstreamReset,
+    IdleTimeout,
+    MaxLifetime,
+    // 被管理接口强制结束
+    Killed,
+    ClientError(io::Error),
+    UpstreamError(io::Error),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Peer {
+    Client,
+    Upstream,
+}
+
+// 明文tcp连接可以走splice，TLS连接只能在用户态解密后复制
+pub trait ClientStream: AsyncRead + AsyncWrite + Unpin + Sized {
+    fn into_tcp(self) -> Result<TcpStream, Self>;
+}
+
+impl ClientStream for TcpStream {
+    fn into_tcp(self) -> Result<TcpStream, Self> {
+        Ok(self)
+    }
+}
+
+impl ClientStream for TlsStream<TcpStream> {
+    fn into_tcp(self) -> Result<TcpStream, Self> {
+        Err(self)
+    }
+}
+
+pub async fn proxy<C>(
+    client: C,
+    upstream: TcpStream,
+    config: &Config,
+    session: &Session,
+    mirror: Option<Mirror>,
+) -> Transfer
+where
+    C: ClientStream,
+{
+    let timeouts = &config.timeouts;
+    let run = async {
+        // 需要把数据复制给shadow时也只能在用户态转发
+        #[cfg(target_os = "linux")]
+        let client = match config.splice && mirror.is_none() {
+            true => match client.into_tcp() {
+                Ok(client) => return splice_streams(client, upstream, config, session).await,
+                Err(client) => client,
+            },
+            false => client,
+        };
+        copy_streams(client, upstream, config, session, mirror).await
+    };
+    // 超过会话最长存活时间或被kill时直接结束，两个方向的复制都会被drop
+    let run = async {
+        match timeouts.max_session {
+            Some(max) => time::timeout(max, run)
+                .await
+                .unwrap_or(CloseReason::MaxLifetime),
+            None => run.await,
+        }
+    };
+    let close = tokio::select! {
+        close = run => close,
+        _ = session.kill.cancelled() => CloseReason::Killed,
+    };
+
+    let transfer = Transfer {
+        client_to_upstream: session.bytes(Direction::ClientToUpstream),
+        upstream_to_client: session.bytes(Direction::UpstreamToClient),
+        close,
+    };
+    debug!(
+        "proxied {} bytes from client to upstream, {} bytes from upstream to client, {}",
+        transfer.client_to_upstream, transfer.upstream_to_client, transfer.close
+    );
+    transfer
+}
+
+async fn copy_streams<C>(
+    client: C,
+    upstream: TcpStream,
+    config: &Config,
+    session: &Session,
+    mirror: Option<Mirror>,
+) -> CloseReason
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let timeouts = &config.timeouts;
+    let bandwidth = config.limits.bandwidth_per_connection;
+    // 流分割client 和 upstream，client可能是明文TcpStream也可能是TlsStream
+    let (mut client_read, mut client_write) = io::split(client);
+    let (mut upstream_read, mut upstream_write) = io::split(upstream);
+
+    // 创建了从客户端到上游服务端的数据复制任务，读client时使用client的空闲超时
+    let c2u = copy_half(
+        &mut client_read,
+        &mut upstream_write,
+        (Peer::Client, Peer::Upstream),
+        timeouts.client_idle_timeout,
+        bandwidth,
+        (session, Direction::ClientToUpstream),
+        mirror,
+    );
+    // 创建了从上游服务端到客户端的数据复制任务
+    let u2c = copy_half(
+        &mut upstream_read,
+        &mut client_write,
+        (Peer::Upstream, Peer::Client),
+        timeouts.upstream_idle_timeout,
+        bandwidth,
+        (session, Direction::UpstreamToClient),
+        None,
+    );
+    relay(c2u, u2c).await
+}
+
+#[cfg(target_os = "linux")]
+async fn splice_streams(
+    mut client: TcpStream,
+    mut upstream: TcpStream,
+    config: &Config,
+    session: &Session,
+) -> CloseReason {
+    let timeouts = &config.timeouts;
+    let bandwidth = config.limits.bandwidth_per_connection;
+    let (mut client_read, mut client_write) = client.split();
+    let (mut upstream_read, mut upstream_write) = upstream.split();
+
+    let c2u = splice_half(
+        &mut client_read,
+        &mut upstream_write,
+        (Peer::Client, Peer::Upstream),
+        timeouts.client_idle_timeout,
+        bandwidth,
+        (session, Direction::ClientToUpstream),
+    );
+    let u2c = splice_half(
+        &mut upstream_read,
+        &mut client_write,
+        (Peer::Upstream, Peer::Client),
+        timeouts.upstream_idle_timeout,
+        bandwidth,
+        (session, Direction::UpstreamToClient),
+    );
+    relay(c2u, u2c).await
+}
+
+// 一个方向正常结束(半关闭)时另一个方向继续转发，任一方向出错则整个会话结束
+async fn relay(
+    c2u: impl Future<Output = Result<(), CloseReason>>,
+    u2c: impl Future<Output = Result<(), CloseReason>>,
+) -> CloseReason {
+    let mut c2u = pin!(c2u);
+    let mut u2c = pin!(u2c);
+    let (mut c2u_done, mut u2c_done) = (false, false);
+    loop {
+        tokio::select! {
+            ret = &mut c2u, if !c2u_done => {
+                c2u_done = true;
+                if let Err(reason) = ret {
+                    return reason;
+                }
+            }
+            ret = &mut u2c, if !u2c_done => {
+                u2c_done = true;
+                if let Err(reason) = ret {
+                    return reason;
+                }
+            }
+            else => return CloseReason::Normal,
+        }
+    }
+}
+
+// 读到EOF后shutdown写端，把FIN传给对端
+async fn copy_half<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    (from, to): (Peer, Peer),
+    idle: Option<Duration>,
+    bandwidth: Option<u64>,
+    (session, direction): (&Session, Direction),
+    mut mirror: Option<Mirror>,
+) -> Result<(), CloseReason>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; 8 * 1024];
+    let mut throttle = bandwidth.map(Throttle::new);
+    loop {
+        let n = with_idle_timeout(idle, session, reader.read(&mut buf))
+            .await
+            .map_err(|e| CloseReason::new(from, e))?;
+        if n == 0 {
+            // drop掉mirror，shadow那边也随之关闭写端
+            drop(mirror);
+            return with_idle_timeout(idle, session, writer.shutdown())
+                .await
+                .map_err(|e| CloseReason::new(to, e));
+        }
+        with_idle_timeout(idle, session, async {
+            writer.write_all(&buf[..n]).await?;
+            writer.flush().await
+        })
+        .await
+        .map_err(|e| CloseReason::new(to, e))?;
+        if let Some(mirror) = &mut mirror {
+            mirror.send(&buf[..n]);
+        }
+        session.add_bytes(direction, n as u64);
+        if let Some(throttle) = &mut throttle {
+            throttle.consume(n).await;
+        }
+    }
+}
+
+// 数据经pipe在内核中从一个socket搬到另一个socket，创建pipe失败或内核不支持时退回copy_half
+#[cfg(target_os = "linux")]
+async fn splice_half(
+    reader: &mut ReadHalf<'_>,
+    writer: &mut WriteHalf<'_>,
+    (from, to): (Peer, Peer),
+    idle: Option<Duration>,
+    bandwidth: Option<u64>,
+    (session, direction): (&Session, Direction),
+) -> Result<(), CloseReason> {
+    let fallback = |reader, writer| {
+        copy_half(
+            reader,
+            writer,
+            (from, to),
+            idle,
+            bandwidth,
+            (session, direction),
+            None,
+        )
+    };
+    let pipe = match Pipe::new() {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            debug!("Failed to create pipe, fall back to userspace copy: {}", e);
+            return fallback(reader, writer).await;
+        }
+    };
+
+    let mut throttle = bandwidth.map(Throttle::new);
+    let mut spliced = false;
+    loop {
+        let n = match with_idle_timeout(idle, session, pipe.splice_in(reader.as_ref())).await {
+            Ok(n) => n,
+            // 只有还没搬运过数据时才能安全地切换到用户态复制
+            Err(e) if !spliced && splice::is_unsupported(&e) => {
+                debug!(
+                    "splice is not supported, fall back to userspace copy: {}",
+                    e
+                );
+                return fallback(reader, writer).await;
+            }
+            Err(e) => return Err(CloseReason::new(from, e)),
+        };
+        spliced = true;
+        if n == 0 {
+            return with_idle_timeout(idle, session, writer.shutdown())
+                .await
+                .map_err(|e| CloseReason::new(to, e));
+        }
+        with_idle_timeout(idle, session, pipe.splice_out(writer.as_ref(), n))
+            .await
+            .map_err(|e| CloseReason::new(to, e))?;
+        session.add_bytes(direction, n as u64);
+        if let Some(throttle) = &mut throttle {
+            throttle.consume(n).await;
+        }
+    }
+}
+
+// 两个方向都没有转发数据超过idle时才超时，单向的长时间下载或上传不会被另一个方向的空闲打断
+async fn with_idle_timeout<T>(
+    idle: Option<Duration>,
+    session: &Session,
+    fut: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    let Some(idle) = idle else {
+        return fut.await;
+    };
+    let mut fut = pin!(fut);
+    loop {
+        let remaining = idle.saturating_sub(session.idle_for());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"));
+        }
+        if let Ok(ret) = time::timeout(remaining, &mut fut).await {
+            return ret;
+        }
+    }
+}
+
+impl CloseReason {
+    fn new(peer: Peer, e: io::Error) -> Self {
+        use io::ErrorKind::*;
+        match (e.kind(), peer) {
+            (TimedOut, _) => Self::IdleTimeout,
+            (ConnectionReset | ConnectionAborted | BrokenPipe, Peer::Client) => Self::ClientReset,
+            (ConnectionReset | ConnectionAborted | BrokenPipe, Peer::Upstream) => {
+                Self::UpstreamReset
+            }
+            (_, Peer::Client) => Self::ClientError(e),
+            (_, Peer::Upstream) => Self::UpstreamError(e),
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Normal => write!(f, "closed normally"),
+            Self::ClientReset => write!(f, "reset by client"),
+            Self::UpstreamReset => write!(f, "reset by upstream"),
+            Self::IdleTimeout => write!(f, "idle timeout"),
+            Self::MaxLifetime => write!(f, "exceeded max session lifetime"),
+            Self::Killed => write!(f, "killed by admin"),
+            Self::ClientError(e) => write!(f, "client error: {}", e),
+            Self::UpstreamError(e) => write!(f, "upstream error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::config::ProxyProtocolVersion;
+use anyhow::{anyhow, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// v1头部(含\r\n)最长107字节
+const V1_MAX_LEN: usize = 107;
+
+// PROXY头携带的真实客户端地址和它连接的目标地址
+#[derive(Debug, Clone, Copy)]
+pub struct ProxyHeader {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+}
+
+// 读取并解析client发来的PROXY头，v1/v2自动识别
+// 只读取头部本身的字节，之后的数据原样留在流中
+// 返回None表示头部合法但不带地址(v1 UNKNOWN / v2 LOCAL)
+pub async fn read_header<R>(reader: &mut R) -> Result<Option<ProxyHeader>>
+where
+    R: AsyncRead + Unpin,
+{
+    // v1最短的"PROXY UNKNOWN\r\n"也有15字节，先读12字节不会读过头
+    let mut buf = vec![0u8; 12];
+    reader.read_exact(&mut buf).await?;
+    if buf[..] == V2_SIGNATURE {
+        read_v2(reader).await
+    } else if buf.starts_with(b"PROXY ") {
+        read_v1(reader, buf).await
+    } else {
+        Err(anyhow!("missing proxy protocol header"))
+    }
+}
+
+async fn read_v1<R>(reader: &mut R, mut buf: Vec<u8>) -> Result<Option<ProxyHeader>>
+where
+    R: AsyncRead + Unpin,
+{
+    // 逐字节读到\r\n，避免多读走后面的业务数据
+    while !buf.ends_with(b"\r\n") {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(anyhow!("proxy protocol v1 header too long"));
+        }
+        buf.push(reader.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&buf[..buf.len() - 2])?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, dst, sport, dport] => Ok(Some(ProxyHeader {
+            src: SocketAddr::new(src.parse()?, sport.parse()?),
+            dst: SocketAddr::new(dst.parse()?, dport.parse()?),
+        })),
+        _ => Err(anyhow!("invalid proxy protocol v1 header: {}", line)),
+    }
+}
+
+async fn read_v2<R>(reader: &mut R) -> Result<Option<ProxyHeader>>
+where
+    R: AsyncRead + Unpin,
+{
+    let ver_cmd = reader.read_u8().await?;
+    let family = reader.read_u8().await?;
+    let len = reader.read_u16().await? as usize;
+    // 地址之后可能还有TLV扩展，整体读出后只解析地址部分
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+
+    if ver_cmd >> 4 != 2 {
+        return Err(anyhow!("unsupported proxy protocol version"));
+    }
+    // LOCAL命令是负载均衡自身的健康检查等连接
+    if ver_cmd & 0x0f == 0 {
+        return Ok(None);
+    }
+    // 高4位是地址族，低4位是传输协议(TCP/UDP)
+    match family >> 4 {
+        1 if len >= 12 => {
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&body[0..4])?);
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&body[4..8])?);
+            Ok(Some(ProxyHeader {
+                src: SocketAddr::new(src.into(), u16::from_be_bytes([body[8], body[9]])),
+                dst: SocketAddr::new(dst.into(), u16::from_be_bytes([body[10], body[11]])),
+            }))
+        }
+        2 if len >= 36 => {
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&body[0..16])?);
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&body[16..32])?);
+            Ok(Some(ProxyHeader {
+                src: SocketAddr::new(src.into(), u16::from_be_bytes([body[32], body[33]])),
+                dst: SocketAddr::new(dst.into(), u16::from_be_bytes([body[34], body[35]])),
+            }))
+        }
+        // UNSPEC或unix socket地址，忽略
+        _ => Ok(None),
+    }
+}
+
+// 生成发给upstream的PROXY头
+pub fn encode_header(version: ProxyProtocolVersion, header: &ProxyHeader) -> Vec<u8> {
+    // 两端地址族不同时统一成IPv6
+    let (src, dst) = match (header.src.ip(), header.dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => (IpAddr::V4(src), IpAddr::V4(dst)),
+        (src, dst) => (IpAddr::V6(to_ipv6(src)), IpAddr::V6(to_ipv6(dst))),
+    };
+    let (sport, dport) = (header.src.port(), header.dst.port());
+
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let proto = if src.is_ipv4() { "TCP4" } else { "TCP6" };
+            format!("PROXY {} {} {} {} {}\r\n", proto, src, dst, sport, dport).into_bytes()
+        }
+        ProxyProtocolVersion::V2 => {
+            let mut buf = V2_SIGNATURE.to_vec();
+            // version 2, PROXY命令
+            buf.push(0x21);
+            let addrs = match (src, dst) {
+                (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                    // TCP over IPv4
+                    buf.push(0x11);
+                    [src.octets().to_vec(), dst.octets().to_vec()].concat()
+                }
+                _ => {
+                    // TCP over IPv6
+                    buf.push(0x21);
+                    [
+                        to_ipv6(src).octets().to_vec(),
+                        to_ipv6(dst).octets().to_vec(),
+                    ]
+                    .concat()
+                }
+            };
+            buf.extend_from_slice(&((addrs.len() + 4) as u16).to_be_bytes());
+            buf.extend_from_slice(&addrs);
+            buf.extend_from_slice(&sport.to_be_bytes());
+            buf.extend_from_slice(&dport.to_be_bytes());
+            buf
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: [ProxyProtocolVersion; 2] =
+        [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2];
+
+    async fn read(data: &[u8]) -> (Result<Option<ProxyHeader>>, &[u8]) {
+        let mut reader = data;
+        let header = read_header(&mut reader).await;
+        (header, reader)
+    }
+
+    #[tokio::test]
+    async fn encoded_header_round_trips() {
+        let pairs = [
+            ("192.168.1.10:51234", "10.0.0.1:443"),
+            ("[2001:db8::1]:51234", "[2001:db8::2]:443"),
+        ];
+        for version in VERSIONS {
+            for (src, dst) in pairs {
+                let header = ProxyHeader {
+                    src: src.parse().unwrap(),
+                    dst: dst.parse().unwrap(),
+                };
+                let mut data = encode_header(version, &header);
+                data.extend_from_slice(b"GET / HTTP/1.1\r\n");
+
+                // 头部之后的业务数据留在流中
+                let (parsed, rest) = read(&data).await;
+                let parsed = parsed.unwrap().unwrap();
+                assert_eq!(parsed.src, header.src);
+                assert_eq!(parsed.dst, header.dst);
+                assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_families_are_encoded_as_ipv6() {
+        let header = ProxyHeader {
+            src: "192.168.1.10:51234".parse().unwrap(),
+            dst: "[2001:db8::2]:443".parse().unwrap(),
+        };
+        for version in VERSIONS {
+            let data = encode_header(version, &header);
+            let parsed = read(&data).await.0.unwrap().unwrap();
+            assert_eq!(parsed.src, "[::ffff:192.168.1.10]:51234".parse().unwrap());
+            assert_eq!(parsed.dst, header.dst);
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_and_local_have_no_address() {
+        let (header, rest) = read(b"PROXY UNKNOWN\r\nrest").await;
+        assert!(header.unwrap().is_none());
+        assert_eq!(rest, b"rest");
+
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(b"rest");
+        let (header, rest) = read(&data).await;
+        assert!(header.unwrap().is_none());
+        assert_eq!(rest, b"rest");
+    }
+
+    #[tokio::test]
+    async fn over_long_v1_header_is_rejected() {
+        let line = format!("PROXY TCP4 {}\r\n", "1".repeat(V1_MAX_LEN));
+        assert!(read(line.as_bytes()).await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn bad_signature_is_rejected() {
+        assert!(read(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.0.is_err());
+
+        // 签名正确但版本号不是2
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x11, 0x11, 0x00, 0x00]);
+        assert!(read(&data).await.0.is_err());
+    }
+}
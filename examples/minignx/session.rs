@@ -0,0 +1,124 @@
+use crate::upstream::Upstream;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+// 正在转发的tcp会话，字节数在转发过程中实时更新
+#[derive(Debug)]
+pub struct Session {
+    pub id: u64,
+    pub client: SocketAddr,
+    pub upstream: Arc<Upstream>,
+    pub started_at: DateTime<Utc>,
+    pub client_to_upstream: AtomicU64,
+    pub upstream_to_client: AtomicU64,
+    // 最后一次转发数据的时间，为created之后的毫秒数，两个方向共用
+    created: Instant,
+    last_active: AtomicU64,
+    // 管理接口通过它结束会话
+    pub kill: CancellationToken,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    ClientToUpstream,
+    UpstreamToClient,
+}
+
+#[derive(Debug, Default)]
+pub struct Sessions {
+    next_id: AtomicU64,
+    sessions: DashMap<u64, Arc<Session>>,
+}
+
+// 会话结束时drop，从活跃列表中移除
+pub struct SessionGuard {
+    sessions: Arc<Sessions>,
+    pub session: Arc<Session>,
+}
+
+impl Sessions {
+    pub fn register(self: &Arc<Self>, client: SocketAddr, upstream: Arc<Upstream>) -> SessionGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        upstream.active.fetch_add(1, Ordering::Relaxed);
+        let session = Arc::new(Session {
+            id,
+            client,
+            upstream,
+            started_at: Utc::now(),
+            client_to_upstream: AtomicU64::new(0),
+            upstream_to_client: AtomicU64::new(0),
+            created: Instant::now(),
+            last_active: AtomicU64::new(0),
+            kill: CancellationToken::new(),
+        });
+        self.sessions.insert(id, session.clone());
+        SessionGuard {
+            sessions: self.clone(),
+            session,
+        }
+    }
+
+    pub fn list(&self) -> Vec<Arc<Session>> {
+        let mut sessions: Vec<_> = self.sessions.iter().map(|s| s.value().clone()).collect();
+        sessions.sort_by_key(|s| s.id);
+        sessions
+    }
+
+    pub fn kill(&self, id: u64) -> bool {
+        match self.sessions.get(&id) {
+            Some(session) => {
+                session.kill.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Session {
+    // 同时累加到会话和upstream的计数上
+    pub fn add_bytes(&self, direction: Direction, n: u64) {
+        let (session, upstream) = match direction {
+            Direction::ClientToUpstream => {
+                (&self.client_to_upstream, &self.upstream.client_to_upstream)
+            }
+            Direction::UpstreamToClient => {
+                (&self.upstream_to_client, &self.upstream.upstream_to_client)
+            }
+        };
+        session.fetch_add(n, Ordering::Relaxed);
+        upstream.fetch_add(n, Ordering::Relaxed);
+        let elapsed = self.created.elapsed().as_millis() as u64;
+        self.last_active.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    // 距离任一方向最后一次转发数据的时间
+    pub fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last_active.load(Ordering::Relaxed));
+        self.created.elapsed().saturating_sub(last)
+    }
+
+    pub fn bytes(&self, direction: Direction) -> u64 {
+        match direction {
+            Direction::ClientToUpstream => self.client_to_upstream.load(Ordering::Relaxed),
+            Direction::UpstreamToClient => self.upstream_to_client.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.sessions.remove(&self.session.id);
+        self.session.upstream.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
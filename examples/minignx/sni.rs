@@ -0,0 +1,240 @@
+use crate::{
+    config::SniRoute,
+    upstream::{Upstream, Upstreams},
+};
+use anyhow::{anyhow, Result};
+use dashmap::DashMap;
+use std::{collections::HashMap, sync::Arc};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// TLS record: content_type(1) + version(2) + length(2)
+const RECORD_HEADER_LEN: usize = 5;
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const MAX_RECORD_LEN: usize = 16384 + 2048;
+
+// 按SNI主机名选择upstream组，不解密TLS，原样转发
+pub struct SniRoutes {
+    names: HashMap<String, usize>,
+    groups: Vec<Upstreams>,
+}
+
+impl SniRoutes {
+    pub fn try_new(routes: &[SniRoute], registry: &DashMap<String, Arc<Upstream>>) -> Result<Self> {
+        let mut names = HashMap::new();
+        let mut groups = Vec::with_capacity(routes.len());
+        for (i, route) in routes.iter().enumerate() {
+            if route.server_names.is_empty() {
+                return Err(anyhow!("sni route requires at least one server name"));
+            }
+            for name in &route.server_names {
+                names.insert(name.to_ascii_lowercase(), i);
+            }
+            groups.push(Upstreams::try_new(&route.upstreams, registry)?);
+        }
+        Ok(Self { names, groups })
+    }
+
+    // 匹配规则和证书选择一致: 先精确匹配，再匹配 *.example.com
+    pub fn route(&self, name: &str) -> Option<&Upstreams> {
+        let name = name.to_ascii_lowercase();
+        let i = match self.names.get(&name) {
+            Some(i) => i,
+            None => {
+                let (_, parent) = name.split_once('.')?;
+                self.names.get(&format!("*.{}", parent))?
+            }
+        };
+        Some(&self.groups[*i])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Upstream>> {
+        self.groups.iter().flat_map(|group| group.iter())
+    }
+}
+
+// 读取第一个TLS record并从ClientHello中取出SNI
+// 读到的字节保存在buf中，连接upstream后需要先原样发出去
+pub async fn read_server_name<R>(reader: &mut R, buf: &mut Vec<u8>) -> Result<Option<String>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    reader.read_exact(&mut header).await?;
+    buf.extend_from_slice(&header);
+    if header[0] != CONTENT_TYPE_HANDSHAKE {
+        return Err(anyhow!("not a tls handshake"));
+    }
+    let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+    if len > MAX_RECORD_LEN {
+        return Err(anyhow!("tls record too large: {}", len));
+    }
+
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    reader.read_exact(&mut buf[start..]).await?;
+    Ok(parse_client_hello(&buf[start..]))
+}
+
+// 没有SNI扩展或者ClientHello被拆到多个record时返回None
+fn parse_client_hello(data: &[u8]) -> Option<String> {
+    let mut r = Reader(data);
+    if r.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let len = r.u24()?;
+    let body = r.bytes(len)?;
+
+    let mut r = Reader(body);
+    // legacy_version(2) + random(32)
+    r.bytes(34)?;
+    let session_id = r.u8()? as usize;
+    r.bytes(session_id)?;
+    let cipher_suites = r.u16()? as usize;
+    r.bytes(cipher_suites)?;
+    let compression = r.u8()? as usize;
+    r.bytes(compression)?;
+
+    let extensions = r.u16()? as usize;
+    let mut r = Reader(r.bytes(extensions)?);
+    while !r.0.is_empty() {
+        let ext_type = r.u16()?;
+        let ext_len = r.u16()? as usize;
+        let ext = r.bytes(ext_len)?;
+        if ext_type != EXTENSION_SERVER_NAME {
+            continue;
+        }
+        // server_name_list: 只看host_name类型(0)
+        let mut r = Reader(ext);
+        let list = r.u16()? as usize;
+        let mut r = Reader(r.bytes(list)?);
+        while !r.0.is_empty() {
+            let name_type = r.u8()?;
+            let name_len = r.u16()? as usize;
+            let name = r.bytes(name_len)?;
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().map(|s| s.to_string());
+            }
+        }
+    }
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.bytes(3)
+            .map(|b| (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_rustls::rustls::{
+        crypto::ring, pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore,
+    };
+
+    // 用rustls生成一个真实的ClientHello record
+    fn client_hello(server_name: &str, enable_sni: bool) -> Vec<u8> {
+        let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        config.enable_sni = enable_sni;
+        let name = ServerName::try_from(server_name.to_string()).unwrap();
+        let mut conn = ClientConnection::new(Arc::new(config), name).unwrap();
+        let mut record = Vec::new();
+        conn.write_tls(&mut record).unwrap();
+        record
+    }
+
+    #[tokio::test]
+    async fn server_name_is_read_from_client_hello() {
+        let record = client_hello("api.example.com", true);
+        let mut data = record.clone();
+        data.extend_from_slice(b"rest");
+
+        // 读到的字节原样保存，之后的数据留在流中
+        let mut reader = data.as_slice();
+        let mut buf = Vec::new();
+        let name = read_server_name(&mut reader, &mut buf).await.unwrap();
+        assert_eq!(name.as_deref(), Some("api.example.com"));
+        assert_eq!(buf, record);
+        assert_eq!(reader, b"rest");
+    }
+
+    #[tokio::test]
+    async fn client_hello_without_sni_has_no_server_name() {
+        let record = client_hello("api.example.com", false);
+        let mut buf = Vec::new();
+        let name = read_server_name(&mut record.as_slice(), &mut buf).await;
+        assert_eq!(name.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn truncated_record_is_rejected() {
+        let record = client_hello("api.example.com", true);
+        let truncated = &record[..record.len() - 1];
+        let mut buf = Vec::new();
+        assert!(read_server_name(&mut &truncated[..], &mut buf)
+            .await
+            .is_err());
+        // record长度之内的ClientHello被截断时解析不出SNI
+        assert_eq!(parse_client_hello(&truncated[RECORD_HEADER_LEN..]), None);
+    }
+
+    #[test]
+    fn exact_names_take_precedence_over_wildcards() {
+        let route = |names: &[&str], upstream: &str| SniRoute {
+            server_names: names.iter().map(|name| name.to_string()).collect(),
+            upstreams: vec![upstream.to_string()],
+        };
+        let routes = [
+            route(&["*.example.com"], "127.0.0.1:9001"),
+            route(&["api.example.com"], "127.0.0.1:9002"),
+        ];
+        let routes = SniRoutes::try_new(&routes, &DashMap::new()).unwrap();
+        let upstream = |name: &str| {
+            routes
+                .route(name)
+                .map(|group| group.iter().next().unwrap().addr.clone())
+        };
+
+        assert_eq!(
+            upstream("api.example.com").as_deref(),
+            Some("127.0.0.1:9002")
+        );
+        assert_eq!(
+            upstream("API.Example.COM").as_deref(),
+            Some("127.0.0.1:9002")
+        );
+        assert_eq!(
+            upstream("www.example.com").as_deref(),
+            Some("127.0.0.1:9001")
+        );
+        // 通配符只匹配一级子域名
+        assert_eq!(upstream("a.b.example.com"), None);
+        assert_eq!(upstream("example.com"), None);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+use tokio::{io::Interest, net::TcpStream};
+
+// 一次最多搬运的字节数，默认pipe容量就是64KB
+const PIPE_SIZE: usize = 64 * 1024;
+
+// splice(2)要求一端是pipe: socket -> pipe -> socket，数据不经过用户态
+pub struct Pipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl Pipe {
+    pub fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        // SAFETY: fds有两个元素，成功后由OwnedFd负责关闭
+        let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe {
+            Ok(Self {
+                read: OwnedFd::from_raw_fd(fds[0]),
+                write: OwnedFd::from_raw_fd(fds[1]),
+            })
+        }
+    }
+
+    // 从socket读入pipe，返回0表示对端关闭了写端
+    pub async fn splice_in(&self, stream: &TcpStream) -> io::Result<usize> {
+        loop {
+            stream.readable().await?;
+            let ret = stream.try_io(Interest::READABLE, || {
+                splice(stream.as_raw_fd(), self.write.as_raw_fd(), PIPE_SIZE)
+            });
+            match ret {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                ret => return ret,
+            }
+        }
+    }
+
+    // 把pipe中的n个字节全部写到socket
+    pub async fn splice_out(&self, stream: &TcpStream, mut n: usize) -> io::Result<()> {
+        while n > 0 {
+            stream.writable().await?;
+            let ret = stream.try_io(Interest::WRITABLE, || {
+                splice(self.read.as_raw_fd(), stream.as_raw_fd(), n)
+            });
+            match ret {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => n -= written,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+// 内核或者socket类型不支持splice，调用方应退回用户态复制
+pub fn is_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP)
+    )
+}
+
+fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    // SAFETY: 两个fd在调用期间都有效，偏移量传空指针表示使用文件当前位置
+    let ret = unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            flags,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
@@ -0,0 +1,148 @@
+use crate::{
+    access_log::{AccessLog, AccessRecord},
+    acl::Acl,
+    config::{resolve_config, Config, Protocol},
+    limit::ConnectionLimiter,
+    metrics::Metrics,
+    session::Sessions,
+    sni::SniRoutes,
+    tls,
+    upstream::{Upstream, Upstreams},
+};
+use anyhow::{anyhow, Result};
+use arc_swap::ArcSwap;
+use dashmap::DashMap;
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+// 由配置构建出的运行时状态，重载配置时整体替换
+// 已建立的连接继续使用旧的Settings，新连接使用新的
+pub struct Settings {
+    pub config: Config,
+    pub upstreams: Upstreams,
+    pub limiter: Arc<ConnectionLimiter>,
+    pub acl: Acl,
+    pub acceptor: Option<TlsAcceptor>,
+    pub routes: Option<SniRoutes>,
+    // udp模式下upstream地址在加载配置时解析一次，新会话直接使用，重载配置时重新解析
+    pub resolved: HashMap<String, SocketAddr>,
+}
+
+impl Settings {
+    pub fn try_new(config: Config, registry: &DashMap<String, Arc<Upstream>>) -> Result<Self> {
+        if config.tls.is_some() && !config.sni_routes.is_empty() {
+            return Err(anyhow!("sni_routes can't be combined with tls termination"));
+        }
+        // 带宽为0时无法计算发送时间
+        if config.limits.bandwidth_per_connection == Some(0) {
+            return Err(anyhow!(
+                "limits.bandwidth_per_connection must be at least 1"
+            ));
+        }
+        // mpsc队列的容量不能为0
+        if config
+            .mirror
+            .as_ref()
+            .is_some_and(|mirror| mirror.queue_size == 0)
+        {
+            return Err(anyhow!("mirror.queue_size must be at least 1"));
+        }
+        let routes = match config.sni_routes.is_empty() {
+            true => None,
+            false => Some(SniRoutes::try_new(&config.sni_routes, registry)?),
+        };
+        let resolved = match config.protocol {
+            Protocol::Udp => resolve_upstreams(&config.upstreams)?,
+            Protocol::Tcp => HashMap::new(),
+        };
+        Ok(Self {
+            upstreams: Upstreams::try_new(&config.upstreams, registry)?,
+            limiter: Arc::new(ConnectionLimiter::new(config.limits.clone())),
+            acl: Acl::try_new(&config.acl)?,
+            // 启用tls时提前加载证书，证书有误直接失败
+            acceptor: config.tls.as_ref().map(tls::build_acceptor).transpose()?,
+            routes,
+            resolved,
+            config,
+        })
+    }
+}
+
+// 阻塞的DNS解析，只在启动和重载配置时调用
+fn resolve_upstreams(addrs: &[String]) -> Result<HashMap<String, SocketAddr>> {
+    addrs
+        .iter()
+        .map(|addr| {
+            let resolved = addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| anyhow!("failed to resolve upstream {}", addr))?;
+            Ok((addr.clone(), resolved))
+        })
+        .collect()
+}
+
+pub struct State {
+    pub config_path: Option<PathBuf>,
+    pub settings: ArcSwap<Settings>,
+    // 按地址保存的upstream状态，不随配置重载丢失
+    pub upstreams: Arc<DashMap<String, Arc<Upstream>>>,
+    pub sessions: Arc<Sessions>,
+    pub metrics: Metrics,
+    pub access_log: Option<AccessLog>,
+}
+
+impl State {
+    pub fn try_new(config_path: Option<PathBuf>) -> Result<Self> {
+        let config = resolve_config(config_path.as_deref())?;
+        Self::with_config(config_path, config)
+    }
+
+    // 测试中直接由Config启动，不经过配置文件
+    pub fn with_config(config_path: Option<PathBuf>, config: Config) -> Result<Self> {
+        let upstreams = Arc::new(DashMap::new());
+        let access_log = config.access_log.as_ref().map(AccessLog::new).transpose()?;
+        let settings = Settings::try_new(config, &upstreams)?;
+        Ok(Self {
+            config_path,
+            settings: ArcSwap::from_pointee(settings),
+            metrics: Metrics::try_new(upstreams.clone())?,
+            upstreams,
+            sessions: Arc::new(Sessions::default()),
+            access_log,
+        })
+    }
+
+    // 重新读取配置文件，新配置有误时保留旧配置
+    // listen_addr不能热更新；连接数限制修改后才重新计数
+    pub fn reload(&self) -> Result<()> {
+        let config = resolve_config(self.config_path.as_deref())?;
+        let current = self.settings.load();
+        if config.listen_addr != current.config.listen_addr {
+            warn!(
+                "listen_addr changed to {}, it will take effect after restart",
+                config.listen_addr
+            );
+        }
+        let mut settings = Settings::try_new(config, &self.upstreams)?;
+        // 已有连接的配额记在旧的limiter上，限制不变时沿用它，否则重载会让上限失效
+        if settings.config.limits == current.config.limits {
+            settings.limiter = current.limiter.clone();
+        }
+        self.settings.store(Arc::new(settings));
+        info!("Config reloaded");
+        Ok(())
+    }
+
+    pub fn log_access(&self, record: AccessRecord) {
+        if let Some(access_log) = &self.access_log {
+            access_log.log(&record);
+        }
+    }
+}
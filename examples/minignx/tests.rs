@@ -0,0 +1,506 @@
+// 端到端测试: 在随机端口上启动echo/sink等upstream，再由Config在进程内启动代理
+use super::serve_tcp;
+use crate::{
+    config::{AccessLogConfig, CertConfig, Config, Protocol, Rotation, TlsConfig},
+    state::State,
+    udp,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{CertificateDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+struct Proxy {
+    addr: SocketAddr,
+    state: Arc<State>,
+    shutdown: CancellationToken,
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+async fn start_proxy(config: Config) -> Proxy {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(State::with_config(None, config).unwrap());
+    let shutdown = CancellationToken::new();
+    tokio::spawn(serve_tcp(
+        state.clone(),
+        listener,
+        shutdown.clone(),
+        TaskTracker::new(),
+    ));
+    Proxy {
+        addr,
+        state,
+        shutdown,
+    }
+}
+
+fn config(upstreams: &[SocketAddr]) -> Config {
+    Config {
+        upstreams: upstreams.iter().map(|addr| addr.to_string()).collect(),
+        listen_addr: "127.0.0.1:0".to_string(),
+        ..Default::default()
+    }
+}
+
+// 把收到的数据原样返回，读到EOF后关闭写端
+async fn echo_upstream() -> SocketAddr {
+    spawn_upstream(|mut stream| async move {
+        let (mut reader, mut writer) = stream.split();
+        io::copy(&mut reader, &mut writer).await?;
+        writer.shutdown().await
+    })
+    .await
+}
+
+// 读到EOF后才回复收到的字节数，用来验证半关闭
+async fn sink_upstream() -> SocketAddr {
+    spawn_upstream(|mut stream| async move {
+        let n = io::copy(&mut stream, &mut io::sink()).await?;
+        stream.write_all(&n.to_be_bytes()).await?;
+        stream.shutdown().await
+    })
+    .await
+}
+
+// 回复自己的名字后关闭，用来统计负载均衡的分布
+async fn named_upstream(name: &'static str) -> SocketAddr {
+    spawn_upstream(move |mut stream| async move {
+        stream.write_all(name.as_bytes()).await?;
+        stream.shutdown().await
+    })
+    .await
+}
+
+// 绑定后立即关闭，连接会被拒绝
+fn dead_upstream() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+async fn spawn_upstream<F, Fut>(handle: F) -> SocketAddr
+where
+    F: Fn(TcpStream) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = io::Result<()>> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle(stream));
+        }
+    });
+    addr
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+// 同时写入和读取，避免数据量超过socket缓冲区时互相等待
+async fn round_trip(addr: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut reader, mut writer) = stream.into_split();
+    let write = async {
+        writer.write_all(data).await.unwrap();
+        writer.shutdown().await.unwrap();
+    };
+    let mut received = Vec::new();
+    let read = reader.read_to_end(&mut received);
+    let (_, ret) = tokio::join!(write, read);
+    ret.unwrap();
+    received
+}
+
+#[tokio::test]
+async fn proxy_preserves_bytes_in_both_directions() {
+    let upstream = echo_upstream().await;
+    let proxy = start_proxy(config(&[upstream])).await;
+
+    let data = payload(4 * 1024 * 1024);
+    let received = round_trip(proxy.addr, &data).await;
+    assert_eq!(received.len(), data.len());
+    assert!(received == data);
+
+    let stats = proxy.state.upstreams.get(&upstream.to_string()).unwrap();
+    let len = data.len() as u64;
+    assert_eq!(stats.client_to_upstream.load(Ordering::Relaxed), len);
+    assert_eq!(stats.upstream_to_client.load(Ordering::Relaxed), len);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn splice_preserves_bytes_in_both_directions() {
+    let upstream = echo_upstream().await;
+    let proxy = start_proxy(Config {
+        splice: true,
+        ..config(&[upstream])
+    })
+    .await;
+
+    let data = payload(4 * 1024 * 1024);
+    let received = round_trip(proxy.addr, &data).await;
+    assert_eq!(received.len(), data.len());
+    assert!(received == data);
+}
+
+#[tokio::test]
+async fn half_close_keeps_upstream_to_client_open() {
+    let upstream = sink_upstream().await;
+    let proxy = start_proxy(config(&[upstream])).await;
+
+    let mut stream = TcpStream::connect(proxy.addr).await.unwrap();
+    stream.write_all(&payload(100_000)).await.unwrap();
+    // client关闭写端后，upstream才会回复
+    stream.shutdown().await.unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, 100_000u64.to_be_bytes());
+}
+
+#[tokio::test]
+async fn failed_upstream_is_retried_on_next_one() {
+    let dead = dead_upstream();
+    let alive = echo_upstream().await;
+    let proxy = start_proxy(config(&[dead, alive])).await;
+
+    // 轮询会先选中dead，失败后重试alive
+    for _ in 0..4 {
+        let received = round_trip(proxy.addr, b"ping").await;
+        assert_eq!(received, b"ping");
+    }
+
+    let dead = proxy.state.upstreams.get(&dead.to_string()).unwrap();
+    assert!(dead.failures.load(Ordering::Relaxed) > 0);
+    assert!(!dead.healthy.load(Ordering::Relaxed));
+    let alive = proxy.state.upstreams.get(&alive.to_string()).unwrap();
+    assert_eq!(alive.connections.load(Ordering::Relaxed), 4);
+}
+
+#[tokio::test]
+async fn client_is_closed_when_all_upstreams_fail() {
+    let mut config = config(&[dead_upstream()]);
+    config.timeouts.connect_retries = 0;
+    let proxy = start_proxy(config).await;
+
+    let mut stream = TcpStream::connect(proxy.addr).await.unwrap();
+    let mut buf = [0u8; 16];
+    // 连接被关闭: 读到EOF或者被reset
+    let ret = stream.read(&mut buf).await;
+    assert!(matches!(ret, Ok(0) | Err(_)));
+}
+
+#[tokio::test]
+async fn connections_are_balanced_round_robin() {
+    let names = ["a", "b", "c"];
+    let mut upstreams = Vec::new();
+    for name in names {
+        upstreams.push(named_upstream(name).await);
+    }
+    let proxy = start_proxy(config(&upstreams)).await;
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for _ in 0..30 {
+        let received = round_trip(proxy.addr, b"").await;
+        *counts
+            .entry(String::from_utf8(received).unwrap())
+            .or_default() += 1;
+    }
+    for name in names {
+        assert_eq!(counts.get(name), Some(&10), "distribution: {:?}", counts);
+    }
+}
+
+#[test]
+fn reload_keeps_limiter_while_limits_are_unchanged() {
+    let path = std::env::temp_dir().join(format!("minignx-{}.yml", nanoid::nanoid!(8)));
+    let mut config = config(&[dead_upstream()]);
+    config.limits.max_connections = Some(1);
+    std::fs::write(&path, serde_yaml::to_string(&config).unwrap()).unwrap();
+    let state = State::with_config(Some(path.clone()), config.clone()).unwrap();
+    let limiter = state.settings.load().limiter.clone();
+
+    // 已有连接的配额仍然记在同一个limiter上
+    state.reload().unwrap();
+    assert!(Arc::ptr_eq(&limiter, &state.settings.load().limiter));
+
+    config.limits.max_connections = Some(2);
+    std::fs::write(&path, serde_yaml::to_string(&config).unwrap()).unwrap();
+    state.reload().unwrap();
+    assert!(!Arc::ptr_eq(&limiter, &state.settings.load().limiter));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn unwritable_access_log_directory_is_an_error() {
+    let mut config = config(&[dead_upstream()]);
+    config.access_log = Some(AccessLogConfig {
+        directory: "/dev/null/logs".into(),
+        file_prefix: "access.log".to_string(),
+        rotation: Default::default(),
+    });
+    assert!(State::with_config(None, config).is_err());
+}
+
+// 每100ms发1个字节，共15个，整个下载期间client一直不发数据
+async fn slow_upstream() -> SocketAddr {
+    spawn_upstream(|mut stream| async move {
+        for i in 0..15u8 {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            stream.write_all(&[i]).await?;
+        }
+        stream.shutdown().await
+    })
+    .await
+}
+
+#[tokio::test]
+async fn quiet_client_is_not_cut_off_while_upstream_is_sending() {
+    let upstream = slow_upstream().await;
+    for splice in [false, true] {
+        let mut config = config(&[upstream]);
+        config.splice = splice;
+        config.timeouts.client_idle_timeout = Some(std::time::Duration::from_millis(500));
+        let proxy = start_proxy(config).await;
+
+        let mut stream = TcpStream::connect(proxy.addr).await.unwrap();
+        stream.write_all(b"GET").await.unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, (0..15).collect::<Vec<u8>>(), "splice: {splice}");
+    }
+}
+
+#[tokio::test]
+async fn idle_session_times_out() {
+    let upstream = sink_upstream().await;
+    let mut config = config(&[upstream]);
+    config.timeouts.client_idle_timeout = Some(std::time::Duration::from_millis(200));
+    let proxy = start_proxy(config).await;
+
+    let mut stream = TcpStream::connect(proxy.addr).await.unwrap();
+    let mut received = Vec::new();
+    let read = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        stream.read_to_end(&mut received),
+    );
+    // 两个方向都没有数据，超时后代理关闭连接
+    assert!(read.await.is_ok());
+    assert!(received.is_empty());
+}
+
+// 把收到的数据报原样发回
+async fn udp_echo_upstream() -> SocketAddr {
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        while let Ok((n, peer)) = upstream.recv_from(&mut buf).await {
+            let _ = upstream.send_to(&buf[..n], peer).await;
+        }
+    });
+    addr
+}
+
+async fn start_udp_proxy(mut config: Config) -> Proxy {
+    config.protocol = Protocol::Udp;
+    let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(State::with_config(None, config).unwrap());
+    let shutdown = CancellationToken::new();
+    tokio::spawn(udp::serve(
+        state.clone(),
+        listener,
+        shutdown.clone(),
+        TaskTracker::new(),
+    ));
+    Proxy {
+        addr,
+        state,
+        shutdown,
+    }
+}
+
+async fn udp_client(proxy: SocketAddr) -> UdpSocket {
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(proxy).await.unwrap();
+    client
+}
+
+#[tokio::test]
+async fn udp_sessions_update_upstream_counters() {
+    let upstream_addr = udp_echo_upstream().await;
+    let proxy = start_udp_proxy(config(&[upstream_addr])).await;
+    let state = &proxy.state;
+
+    let client = udp_client(proxy.addr).await;
+    let mut buf = [0u8; 1024];
+    for _ in 0..2 {
+        client.send(b"ping").await.unwrap();
+        let n = client.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+    }
+
+    // 同一个客户端地址的数据报属于一个会话
+    let upstream = state.upstreams.get(&upstream_addr.to_string()).unwrap();
+    assert_eq!(upstream.connections.load(Ordering::Relaxed), 1);
+    assert_eq!(upstream.active.load(Ordering::Relaxed), 1);
+    assert_eq!(upstream.client_to_upstream.load(Ordering::Relaxed), 8);
+    assert_eq!(upstream.upstream_to_client.load(Ordering::Relaxed), 8);
+    let accepted = state.metrics.connections.with_label_values(&["accepted"]);
+    assert_eq!(accepted.get(), 1);
+}
+
+#[tokio::test]
+async fn udp_sessions_are_capped_by_max_connections() {
+    let mut config = config(&[udp_echo_upstream().await]);
+    config.limits.max_connections = Some(1);
+    let proxy = start_udp_proxy(config).await;
+
+    let mut buf = [0u8; 1024];
+    let first = udp_client(proxy.addr).await;
+    first.send(b"ping").await.unwrap();
+    let n = first.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping");
+
+    // 第二个客户端超过会话上限，数据报被丢弃
+    let second = udp_client(proxy.addr).await;
+    second.send(b"ping").await.unwrap();
+    let recv = tokio::time::timeout(std::time::Duration::from_millis(300), second.recv(&mut buf));
+    assert!(recv.await.is_err());
+    let rejected = proxy
+        .state
+        .metrics
+        .connections
+        .with_label_values(&["rejected"]);
+    assert_eq!(rejected.get(), 1);
+}
+
+fn fixture(name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples/minignx/fixtures")
+        .join(name)
+}
+
+fn fixture_cert(name: &str) -> CertificateDer<'static> {
+    let pem = std::fs::read(fixture(&format!("{name}.pem"))).unwrap();
+    let cert = rustls_pemfile::certs(&mut pem.as_slice())
+        .next()
+        .unwrap()
+        .unwrap();
+    cert
+}
+
+// 两张证书的SAN都是localhost，客户端都信任，通过对端证书区分代理选中了哪一张
+async fn tls_round_trip(
+    addr: SocketAddr,
+    sni: bool,
+    data: &[u8],
+) -> (CertificateDer<'static>, Vec<u8>) {
+    let mut roots = RootCertStore::empty();
+    for name in ["localhost", "default"] {
+        roots.add(fixture_cert(name)).unwrap();
+    }
+    let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.enable_sni = sni;
+    let connector = TlsConnector::from(Arc::new(config));
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut stream = connector.connect(server_name, stream).await.unwrap();
+    let cert = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+    stream.write_all(data).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    (cert, received)
+}
+
+#[tokio::test]
+async fn tls_is_terminated_with_certificate_chosen_by_sni() {
+    let upstream = echo_upstream().await;
+    let mut config = config(&[upstream]);
+    config.tls = Some(TlsConfig {
+        certs: vec![
+            CertConfig {
+                server_names: vec!["localhost".to_string()],
+                cert: fixture("localhost.pem"),
+                key: fixture("localhost.key"),
+            },
+            CertConfig {
+                server_names: vec![],
+                cert: fixture("default.pem"),
+                key: fixture("default.key"),
+            },
+        ],
+    });
+    let proxy = start_proxy(config).await;
+
+    // upstream收到的是解密后的明文
+    let data = payload(64 * 1024);
+    let (cert, received) = tls_round_trip(proxy.addr, true, &data).await;
+    assert_eq!(cert, fixture_cert("localhost"));
+    assert_eq!(received, data);
+
+    // 没有SNI时使用默认证书
+    let (cert, received) = tls_round_trip(proxy.addr, false, b"hello").await;
+    assert_eq!(cert, fixture_cert("default"));
+    assert_eq!(received, b"hello");
+}
+
+#[tokio::test]
+async fn failed_handshake_is_written_to_access_log() {
+    let dir = std::env::temp_dir().join(format!("minignx-{}", nanoid::nanoid!(8)));
+    let mut config = config(&[echo_upstream().await]);
+    config.proxy_protocol.accept = true;
+    config.access_log = Some(AccessLogConfig {
+        directory: dir.clone(),
+        file_prefix: "access.log".to_string(),
+        rotation: Rotation::Never,
+    });
+    let proxy = start_proxy(config).await;
+
+    let mut stream = TcpStream::connect(proxy.addr).await.unwrap();
+    stream.write_all(b"PROXY GARBAGE\r\n").await.unwrap();
+    let mut received = Vec::new();
+    let _ = stream.read_to_end(&mut received).await;
+
+    // 访问日志由后台线程写入文件，等它落盘
+    let path = dir.join("access.log");
+    let mut content = String::new();
+    for _ in 0..50 {
+        content = std::fs::read_to_string(&path).unwrap_or_default();
+        if !content.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let record: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+    assert_eq!(record["upstream"], serde_json::Value::Null);
+    assert!(!record["close_reason"].as_str().unwrap().is_empty());
+    drop(proxy);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,86 @@
+use crate::config::{CertConfig, TlsConfig};
+use anyhow::{anyhow, Result};
+use std::{collections::HashMap, fs::File, io::BufReader, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+// 本地测试可以用openssl生成自签名证书:
+// openssl req -x509 -newkey rsa:2048 -nodes -days 365 -subj "/CN=localhost" \
+//     -addext "subjectAltName=DNS:localhost" -keyout key.pem -out cert.pem
+pub fn build_acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
+    let resolver = SniResolver::try_new(&config.certs)?;
+    let server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+// 按SNI主机名选择证书，找不到时退回默认证书
+#[derive(Debug)]
+struct SniResolver {
+    certs: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    fn try_new(configs: &[CertConfig]) -> Result<Self> {
+        if configs.is_empty() {
+            return Err(anyhow!("tls is enabled but no certificate is configured"));
+        }
+
+        let mut certs = HashMap::new();
+        let mut default = None;
+        for config in configs {
+            let key = Arc::new(load_certified_key(config)?);
+            if config.server_names.is_empty() {
+                default.get_or_insert_with(|| key.clone());
+            }
+            for name in &config.server_names {
+                certs.insert(name.to_ascii_lowercase(), key.clone());
+            }
+        }
+        Ok(Self { certs, default })
+    }
+
+    fn lookup(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        let name = name.to_ascii_lowercase();
+        if let Some(key) = self.certs.get(&name) {
+            return Some(key.clone());
+        }
+        // api.example.com 匹配 *.example.com
+        let (_, parent) = name.split_once('.')?;
+        self.certs.get(&format!("*.{}", parent)).cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.lookup(name))
+            .or_else(|| self.default.clone())
+    }
+}
+
+fn load_certified_key(config: &CertConfig) -> Result<CertifiedKey> {
+    let mut reader = BufReader::new(File::open(&config.cert)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {}", config.cert.display()));
+    }
+
+    let mut reader = BufReader::new(File::open(&config.key)?);
+    let key = rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| anyhow!("no private key found in {}", config.key.display()))?;
+    let key = ring::sign::any_supported_type(&key)?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
@@ -0,0 +1,229 @@
+use crate::{
+    access_log::{millis, AccessRecord},
+    session::Direction,
+    state::State,
+    upstream::Upstream,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{self, Instant},
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, warn};
+
+// udp数据报的最大长度
+const MAX_DATAGRAM: usize = 65535;
+
+type Sessions = Arc<DashMap<SocketAddr, Arc<Session>>>;
+
+// 每个客户端地址对应一个会话，会话独占一个连接到upstream的udp socket
+// upstream的回包从这个socket收到后，再经监听socket发回客户端
+// 字节数和连接数同时累加到共用的Upstream计数上，管理接口和指标与tcp一致
+struct Session {
+    socket: UdpSocket,
+    upstream: Arc<Upstream>,
+    started_at: DateTime<Utc>,
+    created: Instant,
+    last_active: Mutex<Instant>,
+    client_to_upstream: AtomicU64,
+    upstream_to_client: AtomicU64,
+}
+
+// 收到shutdown后不再接收新的数据报，已有会话的回包继续转发直到会话过期
+pub async fn serve(
+    state: Arc<State>,
+    listener: UdpSocket,
+    shutdown: CancellationToken,
+    tracker: TaskTracker,
+) -> Result<()> {
+    let listener = Arc::new(listener);
+    let sessions: Sessions = Arc::new(DashMap::new());
+
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let ret = tokio::select! {
+            ret = listener.recv_from(&mut buf) => ret,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        // windows上对端不可达时recv_from会返回错误，不能因此退出
+        let (n, addr) = match ret {
+            Ok(ret) => ret,
+            Err(e) => {
+                warn!("Failed to receive datagram: {}", e);
+                continue;
+            }
+        };
+
+        let existing = sessions.get(&addr).map(|session| session.clone());
+        let session = match existing {
+            Some(session) => session,
+            None => {
+                // 新会话使用当前配置的快照
+                let settings = state.settings.load_full();
+                if !settings.acl.is_allowed(addr.ip()) {
+                    warn!("Denied datagram from {} by acl", addr);
+                    state.metrics.inc_connections("rejected");
+                    continue;
+                }
+                // 每个会话占用一个socket，达到上限后新客户端的数据报直接丢弃
+                if let Some(max) = settings.config.limits.max_connections {
+                    if sessions.len() >= max {
+                        warn!("Max udp sessions reached, dropped datagram from {}", addr);
+                        state.metrics.inc_connections("rejected");
+                        continue;
+                    }
+                }
+                let Some(upstream) = settings.upstreams.pick() else {
+                    warn!("All upstreams are draining, dropped datagram from {}", addr);
+                    state.metrics.inc_connections("failed");
+                    continue;
+                };
+                let upstream_addr = settings.resolved[&upstream.addr];
+                let session = match Session::try_new(upstream.clone(), upstream_addr).await {
+                    Ok(session) => Arc::new(session),
+                    Err(e) => {
+                        warn!("Failed to create udp session for {}: {:?}", addr, e);
+                        upstream.healthy.store(false, Ordering::Relaxed);
+                        upstream.failures.fetch_add(1, Ordering::Relaxed);
+                        state.metrics.inc_connections("failed");
+                        continue;
+                    }
+                };
+                state.metrics.inc_connections("accepted");
+                debug!(
+                    "New udp session from {} to upstream {}",
+                    addr, session.upstream.addr
+                );
+                sessions.insert(addr, session.clone());
+                tracker.spawn(relay_replies(
+                    state.clone(),
+                    listener.clone(),
+                    addr,
+                    session.clone(),
+                    sessions.clone(),
+                    settings.config.timeouts.udp_session_timeout,
+                ));
+                session
+            }
+        };
+
+        session.touch();
+        match session.socket.send(&buf[..n]).await {
+            Ok(n) => session.add_bytes(Direction::ClientToUpstream, n as u64),
+            Err(e) => warn!(
+                "Failed to send datagram to upstream {}: {}",
+                session.upstream.addr, e
+            ),
+        }
+    }
+}
+
+// 把upstream的回包转给客户端，会话空闲超时或upstream出错时结束并移除会话
+async fn relay_replies(
+    state: Arc<State>,
+    listener: Arc<UdpSocket>,
+    client: SocketAddr,
+    session: Arc<Session>,
+    sessions: Sessions,
+    timeout: Duration,
+) {
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    let close_reason = loop {
+        match time::timeout(timeout, session.socket.recv(&mut buf)).await {
+            Ok(Ok(n)) => {
+                session.touch();
+                match listener.send_to(&buf[..n], client).await {
+                    Ok(n) => session.add_bytes(Direction::UpstreamToClient, n as u64),
+                    Err(e) => warn!("Failed to send datagram to {}: {}", client, e),
+                }
+            }
+            // 通常是upstream端口不可达，移除会话后下一个数据报会重新选择upstream
+            Ok(Err(e)) => {
+                warn!("Upstream {} error: {}", session.upstream.addr, e);
+                session.upstream.healthy.store(false, Ordering::Relaxed);
+                session.upstream.failures.fetch_add(1, Ordering::Relaxed);
+                break format!("upstream error: {}", e);
+            }
+            // 期间客户端可能发过数据，只有两个方向都空闲才算过期
+            Err(_) => {
+                if session.idle_for() >= timeout {
+                    break "idle timeout".to_string();
+                }
+            }
+        }
+    };
+
+    sessions.remove(&client);
+    session.upstream.active.fetch_sub(1, Ordering::Relaxed);
+    state.log_access(AccessRecord {
+        timestamp: session.started_at,
+        protocol: "udp",
+        client,
+        listener: listener
+            .local_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default(),
+        upstream: Some(session.upstream.addr.clone()),
+        connect_ms: None,
+        bytes_in: session.client_to_upstream.load(Ordering::Relaxed),
+        bytes_out: session.upstream_to_client.load(Ordering::Relaxed),
+        duration_ms: millis(session.created.elapsed()),
+        close_reason,
+    });
+}
+
+impl Session {
+    async fn try_new(upstream: Arc<Upstream>, addr: SocketAddr) -> Result<Self> {
+        // 按upstream的地址族绑定本地的随机端口
+        let local: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0)).await?;
+        socket.connect(addr).await?;
+        upstream.healthy.store(true, Ordering::Relaxed);
+        upstream.connections.fetch_add(1, Ordering::Relaxed);
+        upstream.active.fetch_add(1, Ordering::Relaxed);
+        Ok(Self {
+            socket,
+            upstream,
+            started_at: Utc::now(),
+            created: Instant::now(),
+            last_active: Mutex::new(Instant::now()),
+            client_to_upstream: AtomicU64::new(0),
+            upstream_to_client: AtomicU64::new(0),
+        })
+    }
+
+    fn add_bytes(&self, direction: Direction, n: u64) {
+        let (session, upstream) = match direction {
+            Direction::ClientToUpstream => {
+                (&self.client_to_upstream, &self.upstream.client_to_upstream)
+            }
+            Direction::UpstreamToClient => {
+                (&self.upstream_to_client, &self.upstream.upstream_to_client)
+            }
+        };
+        session.fetch_add(n, Ordering::Relaxed);
+        upstream.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn touch(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_active.lock().unwrap().elapsed()
+    }
+}
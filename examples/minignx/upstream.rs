@@ -0,0 +1,119 @@
+use crate::{config::TimeoutConfig, metrics::Metrics};
+use anyhow::{anyhow, Result};
+use dashmap::DashMap;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::{
+    net::TcpStream,
+    time::{self, Instant},
+};
+use tracing::warn;
+
+// 单个upstream的状态和计数，按地址在State中保存，配置重载后继续沿用
+#[derive(Debug)]
+pub struct Upstream {
+    pub addr: String,
+    // 管理接口摘除的upstream不再分配新连接，已有连接不受影响
+    pub draining: AtomicBool,
+    // 最近一次连接是否成功
+    pub healthy: AtomicBool,
+    pub active: AtomicU64,
+    pub connections: AtomicU64,
+    pub failures: AtomicU64,
+    pub client_to_upstream: AtomicU64,
+    pub upstream_to_client: AtomicU64,
+}
+
+pub struct Upstreams {
+    upstreams: Vec<Arc<Upstream>>,
+    next: AtomicUsize,
+}
+
+impl Upstreams {
+    pub fn try_new(addrs: &[String], registry: &DashMap<String, Arc<Upstream>>) -> Result<Self> {
+        if addrs.is_empty() {
+            return Err(anyhow!("at least one upstream is required"));
+        }
+        let upstreams = addrs
+            .iter()
+            .map(|addr| {
+                registry
+                    .entry(addr.clone())
+                    .or_insert_with(|| Arc::new(Upstream::new(addr)))
+                    .clone()
+            })
+            .collect();
+        Ok(Self {
+            upstreams,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Upstream>> {
+        self.upstreams.iter()
+    }
+
+    // 轮询选择下一个没有被摘除的upstream
+    pub fn pick(&self) -> Option<&Arc<Upstream>> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.upstreams.len())
+            .map(|i| &self.upstreams[(start + i) % self.upstreams.len()])
+            .find(|upstream| !upstream.draining.load(Ordering::Relaxed))
+    }
+
+    // 从轮询位置开始连接，失败后退避等待并换下一个upstream重试
+    pub async fn connect(
+        &self,
+        timeouts: &TimeoutConfig,
+        metrics: &Metrics,
+    ) -> Result<(TcpStream, Arc<Upstream>)> {
+        let mut backoff = timeouts.retry_backoff;
+        for attempt in 0..=timeouts.connect_retries {
+            let upstream = self
+                .pick()
+                .ok_or_else(|| anyhow!("all upstreams are draining"))?;
+            let start = Instant::now();
+            let ret = time::timeout(timeouts.connect_timeout, TcpStream::connect(&upstream.addr));
+            match ret.await {
+                Ok(Ok(stream)) => {
+                    metrics
+                        .connect_duration
+                        .with_label_values(&[&upstream.addr])
+                        .observe(start.elapsed().as_secs_f64());
+                    upstream.healthy.store(true, Ordering::Relaxed);
+                    upstream.connections.fetch_add(1, Ordering::Relaxed);
+                    return Ok((stream, upstream.clone()));
+                }
+                Ok(Err(e)) => warn!("Failed to connect to upstream {}: {}", upstream.addr, e),
+                Err(_) => warn!("Timed out connecting to upstream {}", upstream.addr),
+            }
+            upstream.healthy.store(false, Ordering::Relaxed);
+            upstream.failures.fetch_add(1, Ordering::Relaxed);
+            if attempt < timeouts.connect_retries {
+                time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        Err(anyhow!(
+            "no upstream available after {} attempts",
+            timeouts.connect_retries + 1
+        ))
+    }
+}
+
+impl Upstream {
+    fn new(addr: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            draining: AtomicBool::new(false),
+            healthy: AtomicBool::new(true),
+            active: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            client_to_upstream: AtomicU64::new(0),
+            upstream_to_client: AtomicU64::new(0),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    config::AnalyticsConfig,
+    store::{ClickEvent, UrlStore},
+};
+use chrono::Utc;
+use http::{
+    header::{HeaderName, REFERER, USER_AGENT},
+    HeaderMap,
+};
+use std::{net::IpAddr, sync::Arc};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tracing::{debug, info, warn};
+
+// 一次最多写入的事件数
+const BATCH_SIZE: usize = 256;
+// referrer和user agent的最大保存长度
+const MAX_HEADER_LEN: usize = 512;
+
+// 点击事件先放入队列，由后台任务批量写入存储，跳转不等待写入
+#[derive(Clone)]
+pub struct Analytics {
+    tx: Sender<ClickEvent>,
+    key: [u8; 32],
+}
+
+impl Analytics {
+    pub fn spawn(store: Arc<dyn UrlStore>, config: &AnalyticsConfig) -> Self {
+        // 没有配置盐时每次启动随机生成，重启后独立访客会重新计数
+        let salt = match &config.ip_salt {
+            Some(salt) => salt.clone(),
+            None => {
+                info!("No analytics ip_salt configured, using a random one");
+                nanoid::nanoid!(32)
+            }
+        };
+        let key = blake3::derive_key("shortener client ip hash", salt.as_bytes());
+        let (tx, rx) = mpsc::channel(config.queue_size);
+        tokio::spawn(write_events(store, rx));
+        Self { tx, key }
+    }
+
+    // 队列满时丢弃事件，不影响跳转
+    pub fn record(&self, id: &str, client: IpAddr, headers: &HeaderMap) {
+        let event = ClickEvent {
+            id: id.to_string(),
+            at: Utc::now(),
+            referrer: header_value(headers, REFERER),
+            user_agent: header_value(headers, USER_AGENT),
+            ip_hash: self.hash_ip(client),
+        };
+        match self.tx.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => debug!("Click queue is full, dropping event"),
+            Err(TrySendError::Closed(_)) => warn!("Click recorder is gone, dropping event"),
+        }
+    }
+
+    // 只保存加盐的哈希，不保存原始ip
+    fn hash_ip(&self, ip: IpAddr) -> String {
+        blake3::keyed_hash(&self.key, ip.to_string().as_bytes())
+            .to_hex()
+            .to_string()
+    }
+}
+
+async fn write_events(store: Arc<dyn UrlStore>, mut rx: Receiver<ClickEvent>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    // recv_many等待至少一个事件，再把队列里已有的一起取出
+    while rx.recv_many(&mut batch, BATCH_SIZE).await > 0 {
+        if let Err(e) = store.record_clicks(&batch).await {
+            warn!("Failed to record {} clicks: {}", batch.len(), e);
+        }
+        batch.clear();
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    let value = headers.get(name)?.to_str().ok()?;
+    if value.is_empty() {
+        return None;
+    }
+    Some(value.chars().take(MAX_HEADER_LEN).collect())
+}
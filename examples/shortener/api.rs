@@ -0,0 +1,246 @@
+use crate::{
+    error::AppError,
+    store::{ApiKey, Link},
+    AppState,
+};
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRequestParts, Path, Query, State},
+    response::IntoResponse,
+    routing::{get, patch, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use http::{header::AUTHORIZATION, request::Parts, HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+// 管理接口，挂载在 /api 下
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/keys", post(create_key))
+        .route("/links", get(list_links))
+        .route("/links/:id", patch(update_link).delete(delete_link))
+}
+
+// 请求头 Authorization: Bearer <key> 对应的api key，没有或不合法时返回401
+pub struct Owner(pub ApiKey);
+
+// 可选的api key：没有Authorization时为None，有但不合法时仍然返回401
+pub struct MaybeOwner(pub Option<ApiKey>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Owner {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let key = state.authenticate(&parts.headers).await?;
+        key.map(Owner).ok_or(AppError::Unauthorized)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for MaybeOwner {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        Ok(MaybeOwner(state.authenticate(&parts.headers).await?))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateKeyReq {
+    name: String,
+}
+
+// key只在创建时返回一次
+#[derive(Debug, Serialize)]
+struct CreateKeyRes {
+    id: String,
+    name: String,
+    key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    limit: Option<usize>,
+    // 上一页最后一个链接的id
+    after: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ListRes {
+    links: Vec<LinkRes>,
+    // 没有下一页时为null
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateLinkReq {
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct LinkRes {
+    id: String,
+    short_url: String,
+    url: String,
+    expires_at: Option<DateTime<Utc>>,
+    max_clicks: Option<i64>,
+    domain: Option<String>,
+    redirect_status: Option<i32>,
+}
+
+async fn create_key(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    payload: Result<Json<CreateKeyReq>, JsonRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let Json(data) = payload.map_err(|e| AppError::BadRequest(e.body_text()))?;
+    let (key, token) = state.create_api_key(&headers, &data.name).await?;
+    let body = Json(CreateKeyRes {
+        id: key.id,
+        name: key.name,
+        key: token,
+    });
+    Ok((StatusCode::CREATED, body))
+}
+
+async fn list_links(
+    Owner(owner): Owner,
+    Query(query): Query<ListQuery>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let (links, next) = state
+        .list_links(&owner.id, query.after.as_deref(), query.limit)
+        .await?;
+    let links = links.into_iter().map(|link| state.link_res(link)).collect();
+    Ok(Json(ListRes { links, next }))
+}
+
+async fn update_link(
+    Owner(owner): Owner,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    payload: Result<Json<UpdateLinkReq>, JsonRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let Json(data) = payload.map_err(|e| AppError::BadRequest(e.body_text()))?;
+    let link = state.update_link(&owner.id, &id, &data.url).await?;
+    Ok(Json(state.link_res(link)))
+}
+
+async fn delete_link(
+    Owner(owner): Owner,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_link(&owner.id, &id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+impl AppState {
+    // 没有Authorization时返回None
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<ApiKey>, AppError> {
+        let Some(token) = bearer_token(headers)? else {
+            return Ok(None);
+        };
+        match self.store.find_api_key(&hash_key(token)).await? {
+            Some(key) => Ok(Some(key)),
+            None => Err(AppError::Unauthorized),
+        }
+    }
+
+    // 返回保存的key和只展示一次的明文key
+    pub async fn create_api_key(
+        &self,
+        headers: &HeaderMap,
+        name: &str,
+    ) -> Result<(ApiKey, String), AppError> {
+        // 没有配置admin_token时不开放这个接口
+        let Some(admin_token) = &self.config.api.admin_token else {
+            return Err(AppError::NotFound);
+        };
+        // 比较哈希，blake3::Hash的比较是常数时间的
+        let token = bearer_token(headers)?.ok_or(AppError::Unauthorized)?;
+        if blake3::hash(token.as_bytes()) != blake3::hash(admin_token.as_bytes()) {
+            return Err(AppError::Unauthorized);
+        }
+        let token = format!("sk_{}", nanoid::nanoid!(32));
+        let key = ApiKey {
+            id: nanoid::nanoid!(10),
+            name: name.to_string(),
+            key_hash: hash_key(&token),
+            created_at: Utc::now(),
+        };
+        self.store.insert_api_key(&key).await?;
+        info!("Created api key {} ({})", key.id, key.name);
+        Ok((key, token))
+    }
+
+    // 多取一条判断是否还有下一页
+    pub async fn list_links(
+        &self,
+        owner: &str,
+        after: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<(Vec<Link>, Option<String>), AppError> {
+        let api = &self.config.api;
+        let limit = limit.unwrap_or(api.page_size).clamp(1, api.max_page_size);
+        let mut links = self.store.list(owner, after, limit + 1).await?;
+        let next = match links.len() > limit {
+            true => {
+                links.truncate(limit);
+                links.last().map(|link| link.id.clone())
+            }
+            false => None,
+        };
+        Ok((links, next))
+    }
+
+    pub async fn update_link(&self, owner: &str, id: &str, url: &str) -> Result<Link, AppError> {
+        let url = self.check_url(url)?;
+        if !self.store.update_url(id, owner, &url).await? {
+            return Err(AppError::NotFound);
+        }
+        self.cache.invalidate(id);
+        self.store.get(id).await?.ok_or(AppError::NotFound)
+    }
+
+    pub async fn delete_link(&self, owner: &str, id: &str) -> Result<(), AppError> {
+        if !self.store.delete(id, owner).await? {
+            return Err(AppError::NotFound);
+        }
+        self.cache.invalidate(id);
+        Ok(())
+    }
+
+    fn link_res(&self, link: Link) -> LinkRes {
+        LinkRes {
+            short_url: self.short_url(&link.id, link.domain.as_deref()),
+            id: link.id,
+            url: link.url,
+            expires_at: link.expires_at,
+            max_clicks: link.max_clicks,
+            domain: link.domain,
+            redirect_status: link.redirect_status,
+        }
+    }
+}
+
+// Authorization不是Bearer格式时返回401
+fn bearer_token(headers: &HeaderMap) -> Result<Option<&str>, AppError> {
+    let Some(value) = headers.get(AUTHORIZATION) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| Some(token.trim()))
+        .ok_or(AppError::Unauthorized)
+}
+
+// key是高熵的随机串，不需要加盐或慢哈希
+fn hash_key(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_hex().to_string()
+}
@@ -0,0 +1,76 @@
+use crate::{config::CacheConfig, store::Link};
+use anyhow::Result;
+use hashlink::LruCache;
+use prometheus::{IntCounter, Registry};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+struct CacheEntry {
+    // None表示id不存在
+    link: Option<Link>,
+    deadline: Instant,
+}
+
+// 跳转时读取的链接缓存，容量满时淘汰最久没有访问的，过期后重新查询存储
+// 多个实例之间不同步，其他实例的修改最多延迟ttl后生效
+pub struct LinkCache {
+    entries: Mutex<LruCache<String, CacheEntry>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+    hits: IntCounter,
+    misses: IntCounter,
+}
+
+impl LinkCache {
+    pub fn try_new(config: &CacheConfig, registry: &Registry) -> Result<Self> {
+        let hits = IntCounter::new("shortener_cache_hits_total", "Link cache hits")?;
+        let misses = IntCounter::new("shortener_cache_misses_total", "Link cache misses")?;
+        registry.register(Box::new(hits.clone()))?;
+        registry.register(Box::new(misses.clone()))?;
+        Ok(Self {
+            entries: Mutex::new(LruCache::new(config.capacity)),
+            ttl: config.ttl,
+            negative_ttl: config.negative_ttl,
+            hits,
+            misses,
+        })
+    }
+
+    // 外层None表示没有命中，需要查询存储；Some(None)表示已知id不存在
+    pub fn get(&self, id: &str) -> Option<Option<Link>> {
+        let mut entries = self.entries.lock().unwrap();
+        let ret = match entries.get(id) {
+            Some(entry) if entry.deadline > Instant::now() => Some(entry.link.clone()),
+            Some(_) => {
+                entries.remove(id);
+                None
+            }
+            None => None,
+        };
+        match ret {
+            Some(_) => self.hits.inc(),
+            None => self.misses.inc(),
+        }
+        ret
+    }
+
+    pub fn insert(&self, id: &str, link: Option<Link>) {
+        // 不存在的id缓存时间较短，避免新建的链接长时间404
+        let ttl = match link {
+            Some(_) => self.ttl,
+            None => self.negative_ttl,
+        };
+        let entry = CacheEntry {
+            link,
+            deadline: Instant::now() + ttl,
+        };
+        self.entries.lock().unwrap().insert(id.to_string(), entry);
+    }
+
+    // 链接创建、修改或删除后调用
+    pub fn invalidate(&self, id: &str) {
+        self.entries.lock().unwrap().remove(id);
+    }
+}
@@ -0,0 +1,27 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+// 不带子命令时启动服务，兼容之前只传配置文件路径的用法
+#[derive(Debug, Parser)]
+#[command(about = "A url shortener")]
+pub struct Cli {
+    /// Path to the yaml config file, defaults are used when omitted
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Manage the database schema of the configured store
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateCommand {
+    /// Apply pending migrations, the server also does this on startup
+    Run,
+    /// Show which migrations have been applied
+    Info,
+}
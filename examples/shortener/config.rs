@@ -0,0 +1,251 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSeconds};
+use std::{fs::File, path::Path, time::Duration};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub listen_addr: String,
+    // 返回给用户的短链接前缀，如 https://s.example.com，不配置时使用 http://<listen_addr>
+    #[serde(default)]
+    pub public_url: Option<String>,
+    // 链接可以选择的自定义域名，这些域名需要解析到本服务
+    #[serde(default)]
+    pub custom_domains: Vec<String>,
+    // 链接没有指定时使用的跳转状态码，301和308会被浏览器永久缓存
+    #[serde(default = "default_redirect_status")]
+    pub redirect_status: u16,
+    #[serde(default)]
+    pub store: StoreConfig,
+    #[serde(default)]
+    pub id: IdConfig,
+    #[serde(default)]
+    pub alias: AliasConfig,
+    #[serde(default)]
+    pub expiration: ExpirationConfig,
+    #[serde(default)]
+    pub analytics: AnalyticsConfig,
+    #[serde(default)]
+    pub url: UrlConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+}
+
+// 管理接口
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ApiConfig {
+    // 用于创建api key，不配置时不能通过接口创建key
+    pub admin_token: Option<String>,
+    // 列表接口默认和最大的每页条数
+    pub page_size: usize,
+    pub max_page_size: usize,
+}
+
+// 跳转使用的进程内缓存，capacity为0时关闭
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub capacity: usize,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "ttl_secs")]
+    pub ttl: Duration,
+    // 不存在的id的缓存时间
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "negative_ttl_secs")]
+    pub negative_ttl: Duration,
+}
+
+// 目标url的检查
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UrlConfig {
+    // 规范化之后的最大字节数
+    pub max_length: usize,
+    // 禁止缩短的域名，同时禁止其子域名；国际化域名使用punycode形式
+    pub blocked_domains: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AnalyticsConfig {
+    // 等待写入的点击事件上限，超过后丢弃
+    pub queue_size: usize,
+    // 客户端ip哈希的盐，不配置时每次启动随机生成
+    pub ip_salt: Option<String>,
+    // 统计接口返回的来源数量
+    pub top_referrers: usize,
+    // 统计接口按天统计的天数，包括今天
+    pub stats_days: u32,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ExpirationConfig {
+    // 后台清理过期链接的间隔
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "sweep_interval_secs")]
+    pub sweep_interval: Duration,
+}
+
+// 用户自定义的短链接id
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AliasConfig {
+    pub min_length: usize,
+    pub max_length: usize,
+    // 不区分大小写，避免和已有或将来的路由冲突
+    pub reserved: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct IdConfig {
+    pub length: usize,
+    pub max_length: usize,
+    pub alphabet: String,
+    // id冲突时最多尝试的次数
+    pub max_attempts: u32,
+    // 一次生成中冲突达到这个次数后，id长度加一
+    pub grow_after: u32,
+}
+
+// 存储后端，默认使用内存存储，不依赖外部数据库
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StoreConfig {
+    #[default]
+    Memory,
+    // sqlite://shortener.db?mode=rwc 或 sqlite::memory:
+    Sqlite {
+        url: String,
+    },
+    Postgres {
+        url: String,
+    },
+}
+
+impl StoreConfig {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Sqlite { .. } => "sqlite",
+            Self::Postgres { .. } => "postgres",
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addr: "127.0.0.1:9876".to_string(),
+            public_url: None,
+            custom_domains: Vec::new(),
+            redirect_status: default_redirect_status(),
+            store: StoreConfig::default(),
+            id: IdConfig::default(),
+            alias: AliasConfig::default(),
+            expiration: ExpirationConfig::default(),
+            analytics: AnalyticsConfig::default(),
+            url: UrlConfig::default(),
+            cache: CacheConfig::default(),
+            api: ApiConfig::default(),
+        }
+    }
+}
+
+fn default_redirect_status() -> u16 {
+    307
+}
+
+impl Default for AliasConfig {
+    fn default() -> Self {
+        let reserved = [
+            "api", "admin", "health", "metrics", "static", "stats", "login",
+        ];
+        Self {
+            min_length: 3,
+            max_length: 32,
+            reserved: reserved.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            admin_token: None,
+            page_size: 50,
+            max_page_size: 200,
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Default for UrlConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 2048,
+            blocked_domains: Vec::new(),
+        }
+    }
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: 1024,
+            ip_salt: None,
+            top_referrers: 10,
+            stats_days: 30,
+        }
+    }
+}
+
+impl Default for ExpirationConfig {
+    fn default() -> Self {
+        Self {
+            sweep_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Default for IdConfig {
+    fn default() -> Self {
+        Self {
+            length: 6,
+            max_length: 16,
+            alphabet: "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ".to_string(),
+            max_attempts: 5,
+            grow_after: 3,
+        }
+    }
+}
+
+// 没有指定配置文件时使用默认配置
+pub fn resolve_config(path: Option<&Path>) -> Result<Config> {
+    match path {
+        Some(path) => Config::load(path),
+        None => Ok(Config::default()),
+    }
+}
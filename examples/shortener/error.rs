@@ -0,0 +1,91 @@
+use crate::store::StoreError;
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::StatusCode;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::warn;
+
+// handler返回的错误，决定响应的状态码
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("domain {0} is blocked")]
+    BlockedDomain(String),
+    #[error("invalid alias: {0}")]
+    InvalidAlias(String),
+    #[error("alias {0} is already taken")]
+    AliasTaken(String),
+    #[error("missing or invalid api key")]
+    Unauthorized,
+    #[error("not found")]
+    NotFound,
+    // 链接已过期或点击次数用完
+    #[error("link is no longer available")]
+    Gone,
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+// 错误响应的json body，code供客户端判断，message给人看
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_)
+            | Self::InvalidUrl(_)
+            | Self::BlockedDomain(_)
+            | Self::InvalidAlias(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::AliasTaken(_) => StatusCode::CONFLICT,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Gone => StatusCode::GONE,
+            Self::Store(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::InvalidUrl(_) => "invalid_url",
+            Self::BlockedDomain(_) => "blocked_domain",
+            Self::InvalidAlias(_) => "invalid_alias",
+            Self::AliasTaken(_) => "alias_taken",
+            Self::Unauthorized => "unauthorized",
+            Self::NotFound => "not_found",
+            Self::Gone => "gone",
+            Self::Store(_) | Self::Internal(_) => "internal",
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        // 内部错误只记录日志，不把细节返回给客户端
+        let message = match status.is_server_error() {
+            true => {
+                warn!("Failed to handle request: {self}");
+                "internal server error".to_string()
+            }
+            false => self.to_string(),
+        };
+        let body = ErrorBody {
+            code: self.code(),
+            message,
+        };
+        (status, Json(body)).into_response()
+    }
+}
@@ -0,0 +1,102 @@
+use crate::config::{AliasConfig, IdConfig};
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::info;
+
+// 生成短链接id，冲突较多时自动增加长度
+#[derive(Debug)]
+pub struct IdGenerator {
+    alphabet: Vec<char>,
+    length: AtomicUsize,
+    max_length: usize,
+    pub max_attempts: u32,
+    grow_after: u32,
+}
+
+impl IdGenerator {
+    pub fn try_new(config: &IdConfig) -> Result<Self> {
+        let mut alphabet: Vec<char> = config.alphabet.chars().collect();
+        alphabet.sort_unstable();
+        alphabet.dedup();
+        if alphabet.len() < 2 {
+            return Err(anyhow!("id alphabet needs at least 2 distinct characters"));
+        }
+        // 和自定义id使用同样的字符集，其他字符在url中需要转义
+        if !alphabet.iter().all(|c| is_id_char(*c)) {
+            return Err(anyhow!(
+                "id alphabet may only contain letters, digits, '-' and '_'"
+            ));
+        }
+        // nanoid用一个字节的掩码选字符，超过255个会panic
+        if alphabet.len() > 255 {
+            return Err(anyhow!("id alphabet must be at most 255 characters"));
+        }
+        if config.length == 0 || config.length > config.max_length {
+            return Err(anyhow!(
+                "id length must be between 1 and max_length {}",
+                config.max_length
+            ));
+        }
+        if config.max_attempts == 0 {
+            return Err(anyhow!("id max_attempts must be at least 1"));
+        }
+        Ok(Self {
+            alphabet,
+            length: AtomicUsize::new(config.length),
+            max_length: config.max_length,
+            max_attempts: config.max_attempts,
+            grow_after: config.grow_after,
+        })
+    }
+
+    pub fn generate(&self) -> String {
+        let length = self.length.load(Ordering::Relaxed);
+        nanoid::nanoid!(length, &self.alphabet)
+    }
+
+    // 一次生成中连续冲突grow_after次，说明当前长度的id空间已经很密集
+    // 之后的id都加长一位，只保存在内存中，重启后会重新增长
+    pub fn on_conflict(&self, attempt: u32) {
+        if attempt + 1 < self.grow_after {
+            return;
+        }
+        let length = self.length.load(Ordering::Relaxed);
+        if length < self.max_length
+            && self
+                .length
+                .compare_exchange(length, length + 1, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            info!(
+                "Too many id collisions, growing id length to {}",
+                length + 1
+            );
+        }
+    }
+}
+
+// 自定义id只允许字母、数字、'-'和'_'，返回不合法的原因
+pub fn validate_alias(alias: &str, config: &AliasConfig) -> Result<(), String> {
+    let len = alias.chars().count();
+    if len < config.min_length || len > config.max_length {
+        return Err(format!(
+            "length must be between {} and {}",
+            config.min_length, config.max_length
+        ));
+    }
+    if !alias.chars().all(is_id_char) {
+        return Err("only letters, digits, '-' and '_' are allowed".to_string());
+    }
+    if config
+        .reserved
+        .iter()
+        .any(|word| word.eq_ignore_ascii_case(alias))
+    {
+        return Err(format!("{} is reserved", alias));
+    }
+    Ok(())
+}
+
+fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
@@ -0,0 +1,454 @@
+mod analytics;
+mod api;
+mod cache;
+mod cli;
+mod config;
+mod error;
+mod id;
+mod store;
+#[cfg(test)]
+mod tests;
+mod validate;
+
+use analytics::Analytics;
+use anyhow::{anyhow, Result};
+use api::MaybeOwner;
+use axum::{
+    extract::{rejection::JsonRejection, ConnectInfo, Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use cache::LinkCache;
+use chrono::{DateTime, Days, Utc};
+use clap::Parser;
+use cli::{Cli, Command, MigrateCommand};
+use config::{resolve_config, Config, StoreConfig};
+use error::AppError;
+use http::{header::LOCATION, HeaderMap, HeaderValue, StatusCode};
+use id::{validate_alias, IdGenerator};
+use prometheus::{Registry, TextEncoder};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use store::{DailyClicks, Link, ReferrerClicks, StoreError, UrlStore};
+use tokio::{
+    net::TcpListener,
+    time::{self, MissedTickBehavior},
+};
+use tracing::{info, level_filters::LevelFilter, warn};
+use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+use url::Url;
+use validate::{blocked_domain, normalize_url, public_url, redirect_status};
+
+// 派生Deserialize，解构Req
+#[derive(Debug, Deserialize)]
+struct ShortenReq {
+    url: String,
+    // 可选的自定义id
+    #[serde(default)]
+    alias: Option<String>,
+    #[serde(flatten)]
+    options: LinkOptions,
+}
+
+// 链接的可选设置，都不填时链接永久有效，使用默认的域名和跳转状态码
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+struct LinkOptions {
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    max_clicks: Option<i64>,
+    // 必须是配置的custom_domains之一
+    #[serde(default)]
+    domain: Option<String>,
+    #[serde(default)]
+    redirect_status: Option<u16>,
+}
+
+// 派生Serialize，解析Res
+#[derive(Debug, Serialize)]
+struct ShortenRes {
+    url: String,
+}
+
+// 短链接的访问统计
+#[derive(Debug, Serialize)]
+struct StatsRes {
+    id: String,
+    url: String,
+    clicks: i64,
+    unique_visitors: i64,
+    daily: Vec<DailyClicks>,
+    top_referrers: Vec<ReferrerClicks>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    store: Arc<dyn UrlStore>,
+    ids: Arc<IdGenerator>,
+    cache: Arc<LinkCache>,
+    public_url: Arc<Url>,
+    analytics: Analytics,
+    registry: Registry,
+    config: Arc<Config>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let layer = Layer::new().with_filter(LevelFilter::INFO);
+    tracing_subscriber::registry().with(layer).init();
+
+    let cli = Cli::parse();
+    let config = resolve_config(cli.config.as_deref())?;
+    if let Some(Command::Migrate(command)) = cli.command {
+        return migrate(&config.store, command).await;
+    }
+
+    // 在全局状态中保存配置选择的存储后端，连接时执行迁移
+    let state = AppState::try_new(&config).await?;
+    info!("Using {} store", config.store.name());
+    spawn_sweeper(state.store.clone(), config.expiration.sweep_interval);
+
+    // 监听服务地址
+    let listener = TcpListener::bind(&config.listen_addr).await?;
+    info!("Listening on: {}", config.listen_addr);
+
+    let app = app(state);
+
+    // 启动axum框架的服务器，传入监听地址以及路由实例以及处理函数
+    // 统计需要客户端地址，使用带ConnectInfo的service
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+// 注册uri路由器
+fn app(state: AppState) -> Router {
+    Router::new()
+        .route("/", post(shorten))
+        .route("/:id", get(redirect))
+        .route("/metrics", get(metrics))
+        .route("/:id/stats", get(stats))
+        .nest("/api", api::router())
+        .with_state(state)
+}
+
+// 基于axum的handler，它的参数有顺序要求。http的header里的uri、参数等可以在全局State前，而body只能在State后面
+// 带api key创建的链接属于这个key，可以通过管理接口修改
+async fn shorten(
+    MaybeOwner(owner): MaybeOwner,
+    State(state): State<AppState>,
+    payload: Result<Json<ShortenReq>, JsonRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    // 使用json的deserialize解构请求的data内容，解构为ShortenReq
+    // data为解构json类型的body的映射类型，解析失败时也返回json格式的错误
+    let Json(mut data) = payload.map_err(|e| AppError::BadRequest(e.body_text()))?;
+    data.options.validate(Utc::now(), &state.config)?;
+    let owner = owner.as_ref().map(|key| key.id.as_str());
+    let domain = data.options.domain.clone();
+    let id = match &data.alias {
+        Some(alias) => {
+            state
+                .shorten_with_alias(&data.url, alias, data.options, owner)
+                .await?
+        }
+        None => state.shorten(&data.url, data.options, owner).await?,
+    };
+    // 返回json格式的body，其中包括一个url数据
+    let body = Json(ShortenRes {
+        url: state.short_url(&id, domain.as_deref()),
+    });
+    Ok((StatusCode::CREATED, body))
+}
+
+// url在http请求头里面，可以重用的数据，可以放在全局State前
+async fn redirect(
+    Path(id): Path<String>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    req_headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    // resolve向存储后端查询 path 中指定的id的链接，受次数限制的链接记一次点击
+    let link = state.resolve(&id).await?;
+    // 访问记录异步写入，不等待
+    state.analytics.record(&id, client.ip(), &req_headers);
+
+    // 声明一个字段为空的http的header
+    let mut headers = HeaderMap::new();
+
+    // 给header插入location字段，新链接的url都经过校验，旧数据里不合法的url返回500而不是panic
+    let location = HeaderValue::try_from(&link.url)
+        .map_err(|e| anyhow!("url of {id} is not a valid header value: {e}"))?;
+    headers.insert(LOCATION, location);
+
+    // 返回的http状态码：Redirect（重定向），重定向的地址为http文本中的location字段值
+    // 客户端client接收到这个response之后，会重定向到location的url
+    // 永久重定向会被浏览器缓存，之后的访问不经过服务端，无法统计和限制点击
+    Ok((state.redirect_status(&link), headers))
+}
+
+// prometheus文本格式的指标
+async fn metrics(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let body = TextEncoder::new()
+        .encode_to_string(&state.registry.gather())
+        .map_err(anyhow::Error::from)?;
+    Ok(body)
+}
+
+async fn stats(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.stats(&id).await?))
+}
+
+impl AppState {
+    async fn try_new(config: &Config) -> Result<Self> {
+        // 为0时tokio的interval和mpsc channel会panic
+        if config.expiration.sweep_interval.is_zero() {
+            return Err(anyhow!("expiration.sweep_interval_secs must be at least 1"));
+        }
+        if config.analytics.queue_size == 0 {
+            return Err(anyhow!("analytics.queue_size must be at least 1"));
+        }
+        // 分页大小用clamp限制，上限小于下限时会panic
+        if config.api.max_page_size == 0 || config.api.page_size > config.api.max_page_size {
+            return Err(anyhow!(
+                "api.max_page_size must be at least 1 and not less than api.page_size"
+            ));
+        }
+        let store = store::connect(&config.store).await?;
+        let registry = Registry::new();
+        redirect_status(config.redirect_status).map_err(|e| anyhow!(e))?;
+        Ok(Self {
+            analytics: Analytics::spawn(store.clone(), &config.analytics),
+            store,
+            ids: Arc::new(IdGenerator::try_new(&config.id)?),
+            cache: Arc::new(LinkCache::try_new(&config.cache, &registry)?),
+            public_url: Arc::new(public_url(config)?),
+            registry,
+            config: Arc::new(config.clone()),
+        })
+    }
+
+    // 校验并规范化目标url，规范化之后相同的url共用一个id
+    fn check_url(&self, url: &str) -> Result<String, AppError> {
+        let url = normalize_url(url, &self.config.url).map_err(AppError::InvalidUrl)?;
+        if let Some(domain) = blocked_domain(&url, &self.config.url) {
+            return Err(AppError::BlockedDomain(domain.to_string()));
+        }
+        Ok(url.into())
+    }
+
+    // 使用自定义域名时沿用public_url的scheme，不带路径前缀
+    fn short_url(&self, id: &str, domain: Option<&str>) -> String {
+        match domain {
+            Some(domain) => format!("{}://{}/{}", self.public_url.scheme(), domain, id),
+            None => format!("{}/{}", self.public_url.as_str().trim_end_matches('/'), id),
+        }
+    }
+
+    fn redirect_status(&self, link: &Link) -> StatusCode {
+        let code = match link.redirect_status {
+            Some(code) => code as u16,
+            None => self.config.redirect_status,
+        };
+        // 启动和创建链接时已经检查过
+        StatusCode::from_u16(code).unwrap_or(StatusCode::TEMPORARY_REDIRECT)
+    }
+
+    async fn shorten(
+        &self,
+        url: &str,
+        options: LinkOptions,
+        owner: Option<&str>,
+    ) -> Result<String, AppError> {
+        let url = self.check_url(url)?;
+        // 给url生成随机id，url已存在时返回已有的id；id冲突时换一个重试
+        for attempt in 0..self.ids.max_attempts {
+            let link = Link {
+                owner: owner.map(str::to_string),
+                ..options.apply(Link::new(self.ids.generate(), &url))
+            };
+            match self.store.insert(&link).await {
+                Err(StoreError::IdConflict(id)) => {
+                    warn!("Id {id} already exists, retrying");
+                    self.ids.on_conflict(attempt);
+                }
+                ret => {
+                    let id = ret?;
+                    // 这个id之前可能被当作不存在缓存了
+                    self.cache.invalidate(&id);
+                    return Ok(id);
+                }
+            }
+        }
+        Err(anyhow!(
+            "failed to allocate a unique id after {} attempts",
+            self.ids.max_attempts
+        )
+        .into())
+    }
+
+    // 使用用户指定的id，已被其他url占用时返回409
+    async fn shorten_with_alias(
+        &self,
+        url: &str,
+        alias: &str,
+        options: LinkOptions,
+        owner: Option<&str>,
+    ) -> Result<String, AppError> {
+        validate_alias(alias, &self.config.alias).map_err(AppError::InvalidAlias)?;
+        let url = self.check_url(url)?;
+        let link = Link {
+            owner: owner.map(str::to_string),
+            ..options.apply(Link::new(alias, url))
+        };
+        match self.store.get(alias).await? {
+            // 相同的请求是幂等的
+            Some(existing) if existing.same_settings(&link) => return Ok(alias.to_string()),
+            Some(_) => return Err(AppError::AliasTaken(alias.to_string())),
+            None => {}
+        }
+        // alias不复用url已有的id，检查之后可能被并发占用，以插入结果为准
+        match self.store.insert_exclusive(&link).await {
+            Ok(()) => {
+                self.cache.invalidate(alias);
+                Ok(alias.to_string())
+            }
+            Err(StoreError::IdConflict(_)) => Err(AppError::AliasTaken(alias.to_string())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // 先查缓存，没有命中时查询存储并缓存结果，包括不存在的id
+    async fn get_link(&self, id: &str) -> Result<Option<Link>, StoreError> {
+        if let Some(link) = self.cache.get(id) {
+            return Ok(link);
+        }
+        let link = self.store.get(id).await?;
+        self.cache.insert(id, link.clone());
+        Ok(link)
+    }
+
+    // 返回跳转的链接，链接存在但不可用时返回410
+    async fn resolve(&self, id: &str) -> Result<Link, AppError> {
+        let link = self.get_link(id).await?.ok_or(AppError::NotFound)?;
+        let now = Utc::now();
+        // 点击次数必须在存储里原子地检查和计数，不能使用缓存
+        if link.max_clicks.is_some() {
+            let url = self.store.click(id, now).await?.ok_or(AppError::Gone)?;
+            return Ok(Link { url, ..link });
+        }
+        // 修改链接时会清除缓存，过期时间可以直接用缓存的链接判断
+        match link.is_alive(now) {
+            true => Ok(link),
+            false => Err(AppError::Gone),
+        }
+    }
+
+    // 按天统计最近stats_days天（包括今天）的点击
+    async fn stats(&self, id: &str) -> Result<StatsRes, AppError> {
+        let link = self.store.get(id).await?.ok_or(AppError::NotFound)?;
+        let analytics = &self.config.analytics;
+        let days = Days::new(analytics.stats_days.saturating_sub(1).into());
+        let since = Utc::now()
+            .date_naive()
+            .checked_sub_days(days)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .ok_or_else(|| anyhow!("invalid stats_days {}", analytics.stats_days))?
+            .and_utc();
+        let stats = self
+            .store
+            .click_stats(id, since, analytics.top_referrers)
+            .await?;
+        Ok(StatsRes {
+            id: link.id,
+            url: link.url,
+            clicks: stats.total,
+            unique_visitors: stats.unique_visitors,
+            daily: stats.daily,
+            top_referrers: stats.top_referrers,
+        })
+    }
+}
+
+impl LinkOptions {
+    // 检查并规范化，域名统一为小写
+    fn validate(&mut self, now: DateTime<Utc>, config: &Config) -> Result<(), AppError> {
+        if self.expires_at.is_some_and(|at| at <= now) {
+            return Err(AppError::BadRequest(
+                "expires_at must be in the future".to_string(),
+            ));
+        }
+        if self.max_clicks.is_some_and(|max| max < 1) {
+            return Err(AppError::BadRequest(
+                "max_clicks must be at least 1".to_string(),
+            ));
+        }
+        if let Some(domain) = &mut self.domain {
+            if !config
+                .custom_domains
+                .iter()
+                .any(|custom| custom.eq_ignore_ascii_case(domain))
+            {
+                return Err(AppError::BadRequest(format!(
+                    "domain {domain} is not configured"
+                )));
+            }
+            domain.make_ascii_lowercase();
+        }
+        if let Some(code) = self.redirect_status {
+            redirect_status(code).map_err(AppError::BadRequest)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, link: Link) -> Link {
+        Link {
+            expires_at: self.expires_at,
+            max_clicks: self.max_clicks,
+            domain: self.domain.clone(),
+            redirect_status: self.redirect_status.map(i32::from),
+            ..link
+        }
+    }
+}
+
+// migrate子命令，输出每个迁移版本的状态
+async fn migrate(config: &StoreConfig, command: MigrateCommand) -> Result<()> {
+    let apply = matches!(command, MigrateCommand::Run);
+    let infos = store::migrations(config, apply).await?;
+    if infos.is_empty() {
+        println!("{} store has no migrations", config.name());
+    }
+    for info in infos {
+        println!(
+            "{:>6}  {:<8}  {}",
+            info.version,
+            info.state.to_string(),
+            info.description
+        );
+    }
+    Ok(())
+}
+
+// 定期删除过期的链接，过期之后、删除之前的访问返回410，删除之后返回404
+fn spawn_sweeper(store: Arc<dyn UrlStore>, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match store.delete_expired(Utc::now()).await {
+                Ok(0) => {}
+                Ok(n) => info!("Deleted {} expired links", n),
+                Err(e) => warn!("Failed to delete expired links: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,41 @@
+-- 引入迁移之前的表结构，已有的库可能是任意一个旧版本，所有语句都可以重复执行
+CREATE TABLE IF NOT EXISTS urls (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL
+);
+-- 旧版本的表使用CHAR(6)，id长度增长后放不下
+ALTER TABLE urls ALTER COLUMN id TYPE TEXT;
+-- 旧版本的表没有访问限制，且url唯一；受限的链接可以和其他链接共用url
+ALTER TABLE urls
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS max_clicks BIGINT,
+    ADD COLUMN IF NOT EXISTS clicks BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS owner TEXT,
+    ADD COLUMN IF NOT EXISTS domain TEXT,
+    ADD COLUMN IF NOT EXISTS redirect_status INTEGER,
+    -- 不受限的链接由同一个owner的同一个url共用，alias不共用
+    ADD COLUMN IF NOT EXISTS shared BOOLEAN NOT NULL DEFAULT FALSE,
+    DROP CONSTRAINT IF EXISTS urls_url_key;
+CREATE INDEX IF NOT EXISTS urls_url_idx ON urls (url);
+CREATE INDEX IF NOT EXISTS urls_expires_at_idx ON urls (expires_at);
+CREATE INDEX IF NOT EXISTS urls_owner_idx ON urls (owner, id);
+-- 并发插入同一个url时只有一个成功，owner为NULL时也要唯一
+CREATE UNIQUE INDEX IF NOT EXISTS urls_shared_idx ON urls (url, COALESCE(owner, ''))
+    WHERE shared AND expires_at IS NULL AND max_clicks IS NULL AND domain IS NULL AND redirect_status IS NULL;
+
+-- 只保存key的哈希，数据库泄露时key不能直接使用
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS clicks (
+    link_id TEXT NOT NULL,
+    clicked_at TIMESTAMPTZ NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    ip_hash TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS clicks_link_id_idx ON clicks (link_id, clicked_at);
@@ -0,0 +1,38 @@
+-- 引入迁移之前的表结构，IF NOT EXISTS 使已有的库也能记录为已执行
+-- 受限的链接可以和其他链接共用url，url上只建普通索引
+-- 时间保存为UTC的RFC3339文本，按字符串比较就是按时间比较
+CREATE TABLE IF NOT EXISTS urls (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    expires_at TEXT,
+    max_clicks INTEGER,
+    clicks INTEGER NOT NULL DEFAULT 0,
+    owner TEXT,
+    domain TEXT,
+    redirect_status INTEGER,
+    -- 不受限的链接由同一个owner的同一个url共用，alias不共用
+    shared INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS urls_url_idx ON urls (url);
+CREATE INDEX IF NOT EXISTS urls_expires_at_idx ON urls (expires_at);
+CREATE INDEX IF NOT EXISTS urls_owner_idx ON urls (owner, id);
+-- 并发插入同一个url时只有一个成功，owner为NULL时也要唯一
+CREATE UNIQUE INDEX IF NOT EXISTS urls_shared_idx ON urls (url, COALESCE(owner, ''))
+    WHERE shared AND expires_at IS NULL AND max_clicks IS NULL AND domain IS NULL AND redirect_status IS NULL;
+
+-- 只保存key的哈希，数据库泄露时key不能直接使用
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS clicks (
+    link_id TEXT NOT NULL,
+    clicked_at TEXT NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    ip_hash TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS clicks_link_id_idx ON clicks (link_id, clicked_at);
@@ -0,0 +1,292 @@
+mod memory;
+mod postgres;
+mod sqlite;
+
+use crate::config::StoreConfig;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{
+    migrate::{Migrate, Migrator},
+    Database, FromRow, Pool,
+};
+use std::{collections::HashMap, fmt, sync::Arc};
+use thiserror::Error;
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    // 调用方应该换一个id重试
+    #[error("id {0} already exists")]
+    IdConflict(String),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+// 一条短链接，expires_at和max_clicks为空表示不限制，domain和redirect_status为空时使用配置的默认值
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct Link {
+    pub id: String,
+    pub url: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i64>,
+    // 用于max_clicks的计数，只有受次数限制的链接会更新，访问统计见ClickStats
+    pub clicks: i64,
+    // 创建链接的api key的id，匿名创建的链接不能通过管理接口修改
+    pub owner: Option<String>,
+    // 短链接使用的自定义域名
+    pub domain: Option<String>,
+    // 跳转使用的http状态码：301、302、307或308
+    pub redirect_status: Option<i32>,
+}
+
+impl Link {
+    pub fn new(id: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            url: url.into(),
+            expires_at: None,
+            max_clicks: None,
+            clicks: 0,
+            owner: None,
+            domain: None,
+            redirect_status: None,
+        }
+    }
+
+    // 有限制或自定义选项的链接不和同一个url的其他链接共用id
+    pub fn is_exclusive(&self) -> bool {
+        self.expires_at.is_some()
+            || self.max_clicks.is_some()
+            || self.domain.is_some()
+            || self.redirect_status.is_some()
+    }
+
+    // 除了id和计数以外的设置都相同
+    pub fn same_settings(&self, other: &Link) -> bool {
+        self.url == other.url
+            && self.expires_at == other.expires_at
+            && self.max_clicks == other.max_clicks
+            && self.owner == other.owner
+            && self.domain == other.domain
+            && self.redirect_status == other.redirect_status
+    }
+
+    // 没有过期且点击次数没有用完
+    pub fn is_alive(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+            && self.max_clicks.is_none_or(|max| self.clicks < max)
+    }
+}
+
+// 管理接口使用的api key，只保存key的哈希
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// 一次成功跳转的访问记录，ip只保存哈希
+#[derive(Debug, Clone)]
+pub struct ClickEvent {
+    pub id: String,
+    pub at: DateTime<Utc>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_hash: String,
+}
+
+// 按天统计的点击数，date为UTC日期 YYYY-MM-DD
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct DailyClicks {
+    pub date: String,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct ReferrerClicks {
+    pub referrer: String,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClickStats {
+    // 记录下来的全部点击，队列满时丢弃的不计入
+    pub total: i64,
+    pub unique_visitors: i64,
+    // 按日期升序，没有点击的日期不出现
+    pub daily: Vec<DailyClicks>,
+    // 按点击数降序，没有referrer的访问不计入
+    pub top_referrers: Vec<ReferrerClicks>,
+}
+
+// 一个迁移版本在数据库中的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    // 执行失败，需要手动修复数据库并删除_sqlx_migrations中的记录
+    Failed,
+    // 执行之后迁移文件被修改过，启动时会报错
+    Modified,
+    // 数据库执行过但当前版本没有这个迁移文件
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationInfo {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+// 短链接的存储后端，由配置选择具体实现
+#[async_trait]
+pub trait UrlStore: Send + Sync {
+    // 保存链接，不受限的链接在同一个owner下url已经存在时返回已有的id
+    async fn insert(&self, link: &Link) -> Result<String, StoreError>;
+
+    // 保存链接，不和同一个url的其他链接共用id，id已存在时返回IdConflict
+    async fn insert_exclusive(&self, link: &Link) -> Result<(), StoreError>;
+
+    async fn get(&self, id: &str) -> Result<Option<Link>, StoreError>;
+
+    // 原子地检查限制并把点击数加一，返回跳转的url
+    // 链接不存在、已过期或次数用完时返回None
+    async fn click(&self, id: &str, now: DateTime<Utc>) -> Result<Option<String>, StoreError>;
+
+    // 删除now之前过期的链接及其点击记录，返回删除的链接条数
+    async fn delete_expired(&self, now: DateTime<Utc>) -> Result<u64, StoreError>;
+
+    // owner的链接按id升序分页，返回id大于after的最多limit条
+    async fn list(
+        &self,
+        owner: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Link>, StoreError>;
+
+    // 修改owner的链接的目标url，链接不存在或不属于owner时返回false
+    async fn update_url(&self, id: &str, owner: &str, url: &str) -> Result<bool, StoreError>;
+
+    // 删除owner的链接及其点击记录，链接不存在或不属于owner时返回false
+    async fn delete(&self, id: &str, owner: &str) -> Result<bool, StoreError>;
+
+    async fn insert_api_key(&self, key: &ApiKey) -> Result<(), StoreError>;
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError>;
+
+    async fn record_clicks(&self, events: &[ClickEvent]) -> Result<(), StoreError>;
+
+    // 独立访客为全部时间的，按天统计只包括since之后的点击
+    async fn click_stats(
+        &self,
+        id: &str,
+        since: DateTime<Utc>,
+        top_referrers: usize,
+    ) -> Result<ClickStats, StoreError>;
+}
+
+// 共用url的唯一索引冲突由insert的ON CONFLICT处理，这里的冲突只可能来自id
+fn map_insert_error(id: &str, e: sqlx::Error) -> StoreError {
+    match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => {
+            StoreError::IdConflict(id.to_string())
+        }
+        _ => e.into(),
+    }
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Applied => write!(f, "applied"),
+            Self::Pending => write!(f, "pending"),
+            Self::Failed => write!(f, "failed"),
+            Self::Modified => write!(f, "modified"),
+            Self::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+// 连接时会执行还没有执行的迁移
+pub async fn connect(config: &StoreConfig) -> Result<Arc<dyn UrlStore>> {
+    let store: Arc<dyn UrlStore> = match config {
+        StoreConfig::Memory => Arc::new(MemoryStore::default()),
+        StoreConfig::Sqlite { url } => Arc::new(SqliteStore::try_new(url).await?),
+        StoreConfig::Postgres { url } => Arc::new(PostgresStore::try_new(url).await?),
+    };
+    Ok(store)
+}
+
+// 按版本升序返回迁移的状态，apply为true时先执行还没有执行的迁移；内存存储没有迁移
+pub async fn migrations(config: &StoreConfig, apply: bool) -> Result<Vec<MigrationInfo>> {
+    match config {
+        StoreConfig::Memory => Ok(Vec::new()),
+        StoreConfig::Sqlite { url } => {
+            let pool = sqlite::open(url).await?;
+            if apply {
+                sqlite::MIGRATOR.run(&pool).await?;
+            }
+            migration_info(&sqlite::MIGRATOR, &pool).await
+        }
+        StoreConfig::Postgres { url } => {
+            let pool = postgres::open(url).await?;
+            if apply {
+                postgres::MIGRATOR.run(&pool).await?;
+            }
+            migration_info(&postgres::MIGRATOR, &pool).await
+        }
+    }
+}
+
+async fn migration_info<DB>(migrator: &Migrator, pool: &Pool<DB>) -> Result<Vec<MigrationInfo>>
+where
+    DB: Database,
+    DB::Connection: Migrate,
+{
+    let mut conn = pool.acquire().await?;
+    // 还没有执行过迁移的库没有这个表
+    conn.ensure_migrations_table().await?;
+    let failed = conn.dirty_version().await?;
+    let applied: HashMap<_, _> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.checksum))
+        .collect();
+    let mut infos: Vec<_> = migrator
+        .iter()
+        .map(|m| {
+            let state = match applied.get(&m.version) {
+                _ if failed == Some(m.version) => MigrationState::Failed,
+                Some(checksum) if *checksum != m.checksum => MigrationState::Modified,
+                Some(_) => MigrationState::Applied,
+                None => MigrationState::Pending,
+            };
+            MigrationInfo {
+                version: m.version,
+                description: m.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    infos.extend(
+        applied
+            .keys()
+            .filter(|version| !migrator.iter().any(|m| m.version == **version))
+            .map(|version| MigrationInfo {
+                version: *version,
+                description: String::new(),
+                state: MigrationState::Missing,
+            }),
+    );
+    infos.sort_by_key(|info| info.version);
+    Ok(infos)
+}
@@ -0,0 +1,206 @@
+use super::{
+    ApiKey, ClickEvent, ClickStats, DailyClicks, Link, ReferrerClicks, StoreError, UrlStore,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// 进程内存储，重启后数据丢失，用于测试和本地开发
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    links: DashMap<String, Link>,
+    // 不受限链接的(owner, url)到id的反向索引，保证同一个owner的同一个url只有一个id
+    ids: DashMap<(Option<String>, String), String>,
+    clicks: DashMap<String, Vec<ClickEvent>>,
+    // key的哈希到key
+    api_keys: DashMap<String, ApiKey>,
+}
+
+impl MemoryStore {
+    fn insert_link(&self, link: &Link) -> Result<String, StoreError> {
+        match self.links.entry(link.id.clone()) {
+            Entry::Occupied(_) => Err(StoreError::IdConflict(link.id.clone())),
+            Entry::Vacant(by_id) => {
+                by_id.insert(link.clone());
+                Ok(link.id.clone())
+            }
+        }
+    }
+
+    fn owned(&self, id: &str, owner: &str) -> Option<Link> {
+        self.links
+            .get(id)
+            .filter(|link| link.owner.as_deref() == Some(owner))
+            .map(|link| link.clone())
+    }
+
+    // 反向索引里的id可能已经指向新的链接，只删除仍然指向这个链接的，返回是否删除了
+    fn unindex(&self, link: &Link) -> bool {
+        let key = (link.owner.clone(), link.url.clone());
+        !link.is_exclusive() && self.ids.remove_if(&key, |_, id| id == &link.id).is_some()
+    }
+}
+
+#[async_trait]
+impl UrlStore for MemoryStore {
+    async fn insert(&self, link: &Link) -> Result<String, StoreError> {
+        if link.is_exclusive() {
+            return self.insert_link(link);
+        }
+        // 持有url所在分片的锁，并发插入同一个url时只有一个成功
+        // 总是先锁ids再锁links，不会死锁
+        match self.ids.entry((link.owner.clone(), link.url.clone())) {
+            Entry::Occupied(by_url) => Ok(by_url.get().clone()),
+            Entry::Vacant(by_url) => {
+                let id = self.insert_link(link)?;
+                by_url.insert(id.clone());
+                Ok(id)
+            }
+        }
+    }
+
+    async fn insert_exclusive(&self, link: &Link) -> Result<(), StoreError> {
+        self.insert_link(link).map(|_| ())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Link>, StoreError> {
+        Ok(self.links.get(id).map(|link| link.clone()))
+    }
+
+    async fn click(&self, id: &str, now: DateTime<Utc>) -> Result<Option<String>, StoreError> {
+        // get_mut持有分片的写锁，检查和计数之间不会被并发的点击打断
+        let Some(mut link) = self.links.get_mut(id) else {
+            return Ok(None);
+        };
+        if !link.is_alive(now) {
+            return Ok(None);
+        }
+        link.clicks += 1;
+        Ok(Some(link.url.clone()))
+    }
+
+    async fn delete_expired(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        // 过期的链接都是受限链接，不在ids里
+        let mut deleted = 0;
+        self.links.retain(|_, link| {
+            let expired = link.expires_at.is_some_and(|at| at <= now);
+            if expired {
+                self.clicks.remove(&link.id);
+                deleted += 1;
+            }
+            !expired
+        });
+        Ok(deleted)
+    }
+
+    async fn list(
+        &self,
+        owner: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Link>, StoreError> {
+        let mut links: Vec<Link> = self
+            .links
+            .iter()
+            .filter(|link| link.owner.as_deref() == Some(owner))
+            .filter(|link| after.is_none_or(|after| link.id.as_str() > after))
+            .map(|link| link.clone())
+            .collect();
+        links.sort_by(|a, b| a.id.cmp(&b.id));
+        links.truncate(limit);
+        Ok(links)
+    }
+
+    async fn update_url(&self, id: &str, owner: &str, url: &str) -> Result<bool, StoreError> {
+        // 不同时持有links和ids的锁，避免和insert死锁
+        let Some(old) = self.owned(id, owner) else {
+            return Ok(false);
+        };
+        let shared = self.unindex(&old);
+        let Some(mut link) = self.links.get_mut(id) else {
+            return Ok(false);
+        };
+        link.url = url.to_string();
+        let link = link.clone();
+        // 和数据库一致：只有原来共用的链接继续共用，新url已经有共用的链接时不再共用
+        if shared {
+            self.ids
+                .entry((link.owner, link.url))
+                .or_insert_with(|| id.to_string());
+        }
+        Ok(true)
+    }
+
+    async fn delete(&self, id: &str, owner: &str) -> Result<bool, StoreError> {
+        let Some((_, link)) = self
+            .links
+            .remove_if(id, |_, link| link.owner.as_deref() == Some(owner))
+        else {
+            return Ok(false);
+        };
+        self.unindex(&link);
+        self.clicks.remove(id);
+        Ok(true)
+    }
+
+    async fn insert_api_key(&self, key: &ApiKey) -> Result<(), StoreError> {
+        self.api_keys.insert(key.key_hash.clone(), key.clone());
+        Ok(())
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        Ok(self.api_keys.get(key_hash).map(|key| key.clone()))
+    }
+
+    async fn record_clicks(&self, events: &[ClickEvent]) -> Result<(), StoreError> {
+        for event in events {
+            self.clicks
+                .entry(event.id.clone())
+                .or_default()
+                .push(event.clone());
+        }
+        Ok(())
+    }
+
+    async fn click_stats(
+        &self,
+        id: &str,
+        since: DateTime<Utc>,
+        top_referrers: usize,
+    ) -> Result<ClickStats, StoreError> {
+        let Some(events) = self.clicks.get(id) else {
+            return Ok(ClickStats::default());
+        };
+        let visitors: HashSet<&str> = events.iter().map(|e| e.ip_hash.as_str()).collect();
+        let mut daily = BTreeMap::new();
+        let mut referrers = HashMap::new();
+        for event in events.iter() {
+            if let Some(referrer) = &event.referrer {
+                *referrers.entry(referrer.as_str()).or_insert(0) += 1;
+            }
+            if event.at >= since {
+                *daily.entry(event.at.format("%F").to_string()).or_insert(0) += 1;
+            }
+        }
+        let mut referrers: Vec<_> = referrers
+            .into_iter()
+            .map(|(referrer, clicks)| ReferrerClicks {
+                referrer: referrer.to_string(),
+                clicks,
+            })
+            .collect();
+        // 点击数相同时按referrer排序，结果稳定
+        referrers.sort_by(|a, b| b.clicks.cmp(&a.clicks).then(a.referrer.cmp(&b.referrer)));
+        referrers.truncate(top_referrers);
+        Ok(ClickStats {
+            total: events.len() as i64,
+            unique_visitors: visitors.len() as i64,
+            daily: daily
+                .into_iter()
+                .map(|(date, clicks)| DailyClicks { date, clicks })
+                .collect(),
+            top_referrers: referrers,
+        })
+    }
+}
@@ -0,0 +1,289 @@
+use super::{map_insert_error, ApiKey, ClickEvent, ClickStats, Link, StoreError, UrlStore};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    FromRow, QueryBuilder, Sqlite, SqlitePool,
+};
+use std::str::FromStr;
+
+#[derive(Debug, FromRow)]
+struct UrlRecord {
+    #[sqlx(default)]
+    id: String,
+    #[sqlx(default)]
+    url: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    db: SqlitePool,
+}
+
+// 迁移文件在编译时嵌入，新增文件后需要touch本文件才会重新编译
+pub(super) static MIGRATOR: Migrator = sqlx::migrate!("examples/shortener/migrations/sqlite");
+
+// 连接数据库，不执行迁移
+pub(super) async fn open(url: &str) -> Result<SqlitePool> {
+    let options = SqliteConnectOptions::from_str(url)?;
+    // 内存数据库每个连接都是独立的库，只能用一个连接且不能被回收
+    let pool = match url.contains(":memory:") {
+        true => {
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect_with(options)
+                .await?
+        }
+        false => SqlitePool::connect_with(options).await?,
+    };
+    Ok(pool)
+}
+
+impl SqliteStore {
+    pub async fn try_new(url: &str) -> Result<Self> {
+        let pool = open(url).await?;
+        MIGRATOR.run(&pool).await?;
+        Ok(Self { db: pool })
+    }
+}
+
+#[async_trait]
+impl UrlStore for SqliteStore {
+    async fn insert(&self, link: &Link) -> Result<String, StoreError> {
+        if link.is_exclusive() {
+            self.insert_exclusive(link).await?;
+            return Ok(link.id.clone());
+        }
+        // url已经存在或并发插入同一个url时只有一个成功，其他的读出成功的那个
+        let ret = sqlx::query(
+            "INSERT INTO urls (id, url, owner, shared) VALUES (?, ?, ?, TRUE) ON CONFLICT DO NOTHING",
+        )
+        .bind(&link.id)
+        .bind(&link.url)
+        .bind(&link.owner)
+        .execute(&self.db)
+        .await?;
+        if ret.rows_affected() > 0 {
+            return Ok(link.id.clone());
+        }
+        let existing: Option<UrlRecord> =
+            sqlx::query_as("SELECT id FROM urls WHERE url = ? AND owner IS ? AND shared")
+                .bind(&link.url)
+                .bind(&link.owner)
+                .fetch_optional(&self.db)
+                .await?;
+        // 没有冲突的url，说明是id冲突
+        existing
+            .map(|existing| existing.id)
+            .ok_or_else(|| StoreError::IdConflict(link.id.clone()))
+    }
+
+    async fn insert_exclusive(&self, link: &Link) -> Result<(), StoreError> {
+        sqlx::query(
+            "INSERT INTO urls (id, url, expires_at, max_clicks, owner, domain, redirect_status) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&link.id)
+        .bind(&link.url)
+        .bind(link.expires_at)
+        .bind(link.max_clicks)
+        .bind(&link.owner)
+        .bind(&link.domain)
+        .bind(link.redirect_status)
+        .execute(&self.db)
+        .await
+        .map_err(|e| map_insert_error(&link.id, e))?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Link>, StoreError> {
+        let ret = sqlx::query_as(
+            "SELECT id, url, expires_at, max_clicks, clicks, owner, domain, redirect_status FROM urls WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(ret)
+    }
+
+    async fn click(&self, id: &str, now: DateTime<Utc>) -> Result<Option<String>, StoreError> {
+        // 条件和计数在同一条UPDATE里，并发点击不会超过max_clicks
+        let ret: Option<UrlRecord> = sqlx::query_as(
+            r#"
+            UPDATE urls SET clicks = clicks + 1
+            WHERE id = ?
+                AND (expires_at IS NULL OR expires_at > ?)
+                AND (max_clicks IS NULL OR clicks < max_clicks)
+            RETURNING url
+            "#,
+        )
+        .bind(id)
+        .bind(now)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(ret.map(|r| r.url))
+    }
+
+    async fn delete_expired(&self, now: DateTime<Utc>) -> Result<u64, StoreError> {
+        // id之后可能被新链接使用，点击记录和链接一起删除
+        let mut tx = self.db.begin().await?;
+        sqlx::query(
+            "DELETE FROM clicks WHERE link_id IN (SELECT id FROM urls WHERE expires_at <= ?)",
+        )
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        let ret = sqlx::query("DELETE FROM urls WHERE expires_at <= ?")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(ret.rows_affected())
+    }
+
+    async fn list(
+        &self,
+        owner: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Link>, StoreError> {
+        // 按id翻页，after为空时从头开始
+        let links = sqlx::query_as(
+            r#"
+            SELECT id, url, expires_at, max_clicks, clicks, owner, domain, redirect_status
+            FROM urls
+            WHERE owner = ? AND id > ?
+            ORDER BY id
+            LIMIT ?
+            "#,
+        )
+        .bind(owner)
+        .bind(after.unwrap_or_default())
+        .bind(limit as i64)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(links)
+    }
+
+    async fn update_url(&self, id: &str, owner: &str, url: &str) -> Result<bool, StoreError> {
+        // 改到另一个共用的url时不再共用，避免违反唯一索引
+        let ret = sqlx::query(
+            "UPDATE urls SET url = ?1, shared = shared AND NOT EXISTS (SELECT 1 FROM urls o WHERE o.url = ?1 AND o.owner = ?3 AND o.shared AND o.id <> ?2) WHERE id = ?2 AND owner = ?3",
+        )
+        .bind(url)
+        .bind(id)
+        .bind(owner)
+        .execute(&self.db)
+        .await?;
+        Ok(ret.rows_affected() > 0)
+    }
+
+    async fn delete(&self, id: &str, owner: &str) -> Result<bool, StoreError> {
+        let mut tx = self.db.begin().await?;
+        let ret = sqlx::query("DELETE FROM urls WHERE id = ? AND owner = ?")
+            .bind(id)
+            .bind(owner)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM clicks WHERE link_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn insert_api_key(&self, key: &ApiKey) -> Result<(), StoreError> {
+        sqlx::query("INSERT INTO api_keys (id, name, key_hash, created_at) VALUES (?, ?, ?, ?)")
+            .bind(&key.id)
+            .bind(&key.name)
+            .bind(&key.key_hash)
+            .bind(key.created_at)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        let key = sqlx::query_as(
+            "SELECT id, name, key_hash, created_at FROM api_keys WHERE key_hash = ?",
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(key)
+    }
+
+    async fn record_clicks(&self, events: &[ClickEvent]) -> Result<(), StoreError> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        // 一条INSERT写入整批事件
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "INSERT INTO clicks (link_id, clicked_at, referrer, user_agent, ip_hash) ",
+        );
+        query.push_values(events, |mut row, event| {
+            row.push_bind(&event.id)
+                .push_bind(event.at)
+                .push_bind(&event.referrer)
+                .push_bind(&event.user_agent)
+                .push_bind(&event.ip_hash);
+        });
+        query.build().execute(&self.db).await?;
+        Ok(())
+    }
+
+    async fn click_stats(
+        &self,
+        id: &str,
+        since: DateTime<Utc>,
+        top_referrers: usize,
+    ) -> Result<ClickStats, StoreError> {
+        let (total, unique_visitors) = sqlx::query_as(
+            "SELECT COUNT(*), COUNT(DISTINCT ip_hash) FROM clicks WHERE link_id = ?",
+        )
+        .bind(id)
+        .fetch_one(&self.db)
+        .await?;
+        // clicked_at为RFC3339文本，前10个字符就是UTC日期
+        let daily = sqlx::query_as(
+            r#"
+            SELECT substr(clicked_at, 1, 10) AS date, COUNT(*) AS clicks
+            FROM clicks
+            WHERE link_id = ? AND clicked_at >= ?
+            GROUP BY date
+            ORDER BY date
+            "#,
+        )
+        .bind(id)
+        .bind(since)
+        .fetch_all(&self.db)
+        .await?;
+        let top_referrers = sqlx::query_as(
+            r#"
+            SELECT referrer, COUNT(*) AS clicks
+            FROM clicks
+            WHERE link_id = ? AND referrer IS NOT NULL
+            GROUP BY referrer
+            ORDER BY clicks DESC, referrer
+            LIMIT ?
+            "#,
+        )
+        .bind(id)
+        .bind(top_referrers as i64)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(ClickStats {
+            total,
+            unique_visitors,
+            daily,
+            top_referrers,
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use crate::config::{Config, UrlConfig};
+use anyhow::{anyhow, Result};
+use http::StatusCode;
+use url::{Host, Url};
+
+// 解析并规范化目标url：scheme和域名小写、去掉默认端口、空路径补成'/'，返回不合法的原因
+pub fn normalize_url(input: &str, config: &UrlConfig) -> Result<Url, String> {
+    let input = input.trim();
+    if input.len() > config.max_length {
+        return Err(format!("must be at most {} bytes", config.max_length));
+    }
+    let url = Url::parse(input).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("scheme {} is not allowed", url.scheme()));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err("host is required".to_string());
+    }
+    // 带用户名密码的url常被用来伪装域名，如 https://bank.com@evil.com
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err("credentials are not allowed".to_string());
+    }
+    // 规范化之后可能变长，比如中文路径被百分号编码
+    if url.as_str().len() > config.max_length {
+        return Err(format!("must be at most {} bytes", config.max_length));
+    }
+    Ok(url)
+}
+
+// 域名本身或其子域名在黑名单中时，返回匹配的黑名单项
+pub fn blocked_domain<'a>(url: &Url, config: &'a UrlConfig) -> Option<&'a str> {
+    let Some(Host::Domain(host)) = url.host() else {
+        return None;
+    };
+    // 末尾的'.'表示完整域名，evil.com. 和 evil.com 是同一个域名
+    let host = host.trim_end_matches('.');
+    config
+        .blocked_domains
+        .iter()
+        .map(String::as_str)
+        .find(|domain| {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            host == domain
+                || host
+                    .strip_suffix(&domain)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+}
+
+// 短链接的前缀，启动时检查
+pub fn public_url(config: &Config) -> Result<Url> {
+    let url = match &config.public_url {
+        Some(url) => url.clone(),
+        None => format!("http://{}", config.listen_addr),
+    };
+    let url = Url::parse(&url).map_err(|e| anyhow!("invalid public_url {url}: {e}"))?;
+    if !matches!(url.scheme(), "http" | "https") || url.cannot_be_a_base() {
+        return Err(anyhow!("public_url must be an http or https url"));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(anyhow!("public_url must not have a query or fragment"));
+    }
+    Ok(url)
+}
+
+// 只允许重定向状态码，其他3xx不会让浏览器跳转到Location
+pub fn redirect_status(code: u16) -> Result<StatusCode, String> {
+    match code {
+        301 | 302 | 307 | 308 => Ok(StatusCode::from_u16(code).map_err(|e| e.to_string())?),
+        _ => Err(format!(
+            "redirect_status {code} is not one of 301, 302, 307, 308"
+        )),
+    }
+}